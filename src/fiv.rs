@@ -20,12 +20,16 @@ mod cmdline;
 mod codecs;
 mod files;
 mod image;
+mod session;
+mod trash;
 mod util;
 
 pub use cmdline::Args as CommandLineArgs;
 pub use cmdline::Filenames as CommandLineFilenames;
-pub use files::{Files, Navigate};
-pub use image::{AFPoint, Image, Mark, Orientation, Rotate};
+pub use codecs::Cancel;
+pub use files::{Current, Files, Filter, Navigate};
+pub use image::{AFPoint, Failure, Image, Mark, Orientation, Partial, Rotate};
+pub use session::{SessionView, ZoomMode};
 pub use util::Waitable;
 pub use util::exiv2_byte_order::{ByteOrder, byte_order_of};
 pub use util::numeric;
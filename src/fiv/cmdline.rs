@@ -16,6 +16,8 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+use anyhow::Error;
+use gtk::cairo;
 use log::error;
 use parse_size::parse_size;
 use std::collections::VecDeque;
@@ -23,6 +25,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, atomic};
+use std::time::Duration;
 
 #[derive(Debug, Default, clap::Parser)]
 #[command(
@@ -45,19 +48,44 @@ pub struct Args {
 		default_value_t = 100, env("FIV_PRELOAD_COUNT"))]
 	pub preload_count: u32,
 
-	/// Limit preload memory use
+	/// Limit preload memory use [default: half of the available memory]
 	#[arg(short = 'L', long = "preload-memory", value_names = ["BYTES"],
-		value_parser = |s: &str| parse_size(s), default_value = match size_of::<usize>() {
-			0..=4 => "1GiB",
-			5.. => "20GiB",
-		},
-		env("FIV_PRELOAD_MEMORY"))]
-	pub preload_memory: u64,
+		value_parser = |s: &str| parse_size(s), env("FIV_PRELOAD_MEMORY"))]
+	pub preload_memory: Option<u64>,
+
+	/// Limit memory use for images loaded at full resolution when zooming in
+	/// [default: a quarter of the available memory]
+	#[arg(long = "full-memory", value_names = ["BYTES"],
+		value_parser = |s: &str| parse_size(s), env("FIV_FULL_MEMORY"))]
+	pub full_memory: Option<u64>,
 
 	/// Location to use to mark images using symlinks
 	#[arg(short, long, value_names = ["PATH"])]
 	pub mark_directory: Option<PathBuf>,
 
+	/// Filter to use when enlarging images
+	#[arg(long, value_enum, value_names = ["FILTER"],
+		default_value_t = ScaleFilter::Nearest, env("FIV_ENLARGE_FILTER"))]
+	pub enlarge_filter: ScaleFilter,
+
+	/// Filter to use when reducing images
+	#[arg(long, value_enum, value_names = ["FILTER"],
+		default_value_t = ScaleFilter::Good, env("FIV_REDUCE_FILTER"))]
+	pub reduce_filter: ScaleFilter,
+
+	/// Distance to move the image when panning with the keyboard or scroll wheel
+	#[arg(long, value_names = ["PIXELS"], default_value_t = 50, env("FIV_PAN_STEP"))]
+	pub pan_step: u32,
+
+	/// Time to show each image for in a slideshow
+	#[arg(long, value_names = ["SECONDS"], value_parser = parse_seconds,
+		default_value = "5", env("FIV_SLIDESHOW_INTERVAL"))]
+	pub slideshow_interval: Duration,
+
+	/// Ask for confirmation before moving images to the trash
+	#[arg(long)]
+	pub confirm_trash: bool,
+
 	/// Image files or directories of image files to display
 	#[arg(value_names = ["FILE"], default_value = ".")]
 	pub filenames: Vec<PathBuf>,
@@ -67,6 +95,32 @@ pub struct Args {
 	pub verbose: u8,
 }
 
+fn parse_seconds(value: &str) -> Result<Duration, Error> {
+	Ok(Duration::try_from_secs_f64(value.parse()?)?)
+}
+
+#[derive(Debug, Default, Copy, Clone, clap::ValueEnum)]
+pub enum ScaleFilter {
+	Fast,
+	#[default]
+	Good,
+	Best,
+	Nearest,
+	Bilinear,
+}
+
+impl From<ScaleFilter> for cairo::Filter {
+	fn from(filter: ScaleFilter) -> Self {
+		match filter {
+			ScaleFilter::Fast => cairo::Filter::Fast,
+			ScaleFilter::Good => cairo::Filter::Good,
+			ScaleFilter::Best => cairo::Filter::Best,
+			ScaleFilter::Nearest => cairo::Filter::Nearest,
+			ScaleFilter::Bilinear => cairo::Filter::Bilinear,
+		}
+	}
+}
+
 #[expect(clippy::struct_field_names, reason = "Naming things is hard")]
 pub struct Filenames<'a> {
 	filenames: core::slice::Iter<'a, PathBuf>,
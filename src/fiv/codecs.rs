@@ -16,14 +16,24 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+#[cfg(test)]
+mod benchmark;
 mod generic;
 mod heif;
 mod jpeg;
+mod swizzle;
 
-use super::{Orientation, image::AFPoint, image::ImageData, numeric::DimensionsU32};
-use anyhow::{Error, anyhow};
+use super::{
+	Orientation,
+	image::{AFPoint, ImageData, Partial},
+	numeric::DimensionsU32,
+};
+use anyhow::{Error, bail, ensure};
 use enum_dispatch::enum_dispatch;
-use std::{fmt, sync::LazyLock};
+use std::ffi::OsStr;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, LazyLock};
+use std::{fmt, path::Path};
 
 /// Exiv2 initialisation is not thread-safe
 static EXIV2_INIT: LazyLock<()> = LazyLock::new(|| rexiv2::initialize().unwrap());
@@ -31,7 +41,37 @@ static EXIV2_INIT: LazyLock<()> = LazyLock::new(|| rexiv2::initialize().unwrap()
 #[enum_dispatch]
 pub trait Codec {
 	fn metadata(&self, file: &[u8]) -> Result<CodecMetadata, Error>;
-	fn primary(&self, file: &[u8], metadata: &CodecMetadata) -> Result<CodecPrimary, Error>;
+
+	/// Dimensions that `primary()` will decode the image at when it only
+	/// needs to be at least as large as `minimum`, for codecs that can decode
+	/// images at a reduced size
+	fn decode_dimensions(
+		&self,
+		metadata: &CodecMetadata,
+		_minimum: DimensionsU32,
+	) -> DimensionsU32 {
+		metadata.dimensions
+	}
+
+	/// Stops early with an error if `cancel` is set while decoding
+	fn primary(
+		&self,
+		file: &[u8],
+		metadata: &CodecMetadata,
+		minimum: DimensionsU32,
+		cancel: &Cancel,
+	) -> Result<CodecPrimary, Error>;
+
+	/// Modify `output` (a copy of `file`) so that the image has a new
+	/// orientation
+	fn save_orientation(
+		&self,
+		_file: &[u8],
+		orientation: Orientation,
+		output: &Path,
+	) -> Result<(), Error> {
+		save_exif_orientation(orientation, output)
+	}
 }
 
 #[derive(Debug)]
@@ -39,15 +79,43 @@ pub struct CodecMetadata {
 	pub dimensions: DimensionsU32,
 	pub orientation: Orientation,
 	pub af_points: Option<Vec<AFPoint>>,
+
+	/// Bits per colour channel in the file, before decoding to 8 bits
+	pub bit_depth: Option<u8>,
 }
 
 #[derive(Debug)]
 pub struct CodecPrimary {
 	pub image_data: ImageData,
+
+	/// The file is truncated or corrupt so only part of the image was
+	/// decoded, with the number of rows at the size it was decoded at
+	pub partial: Option<Partial>,
+}
+
+/// Cancellation of a decode that is no longer required
+#[derive(Debug, Default, Clone)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+	pub fn cancel(&self) {
+		self.0.store(true, atomic::Ordering::Release);
+	}
+
+	pub fn cancelled(&self) -> bool {
+		self.0.load(atomic::Ordering::Acquire)
+	}
+
+	pub fn check(&self) -> Result<(), Error> {
+		if self.cancelled() {
+			bail!("Cancelled");
+		}
+		Ok(())
+	}
 }
 
 #[enum_dispatch(Codec)]
-#[derive(strum::AsRefStr)]
+#[derive(Copy, Clone, PartialEq, strum::AsRefStr, strum::IntoStaticStr)]
 pub enum Codecs {
 	Generic,
 	Heif,
@@ -55,34 +123,202 @@ pub enum Codecs {
 }
 
 impl Codecs {
-	pub fn new(file: &[u8]) -> Result<Self, Error> {
+	/// Codecs that may be able to decode the file, in the order that they
+	/// should be tried
+	///
+	/// The content of the file determines the type of image, with the
+	/// extension adding alternatives if that fails, or determining the type
+	/// when the content isn't recognised as an image (e.g. HEIF detected as
+	/// MP4)
+	pub fn candidates(file: &[u8], filename: &Path) -> Result<Vec<Self>, Error> {
 		let mime_type = tree_magic_mini::from_u8(file);
+		let mut candidates = Vec::new();
+
+		if mime_type.starts_with("image/") {
+			candidates.extend(Self::by_mime_type(mime_type));
+		}
+
+		for codec in Self::by_filename(filename) {
+			if !candidates.contains(&codec) {
+				candidates.push(codec);
+			}
+		}
+
+		ensure!(!candidates.is_empty(), "Unsupported type {mime_type}");
+		Ok(candidates)
+	}
 
-		if let Some(codec) = match mime_type {
-			"image/avif" | "image/heic" | "image/heif" => Some(Codecs::from(Heif::default())),
-			"image/jpeg" => Some(Codecs::from(Jpeg::default())),
-			_ => None,
-		} {
-			Ok(codec)
-		} else if mime_type.starts_with("image/") {
-			Ok(Codecs::from(Generic::default()))
-		} else {
-			Err(anyhow!("Unsupported type {}", mime_type))
+	fn by_mime_type(mime_type: &str) -> Vec<Self> {
+		match mime_type {
+			"image/avif" | "image/heic" | "image/heif" => vec![
+				Codecs::from(Heif::default()),
+				Codecs::from(Generic::default()),
+			],
+			"image/jpeg" => vec![
+				Codecs::from(Jpeg::default()),
+				Codecs::from(Generic::default()),
+			],
+			_ => vec![Codecs::from(Generic::default())],
+		}
+	}
+
+	/// Codecs for the type of image that the extension of the filename
+	/// indicates, if any
+	pub fn by_filename(filename: &Path) -> Vec<Self> {
+		filename
+			.extension()
+			.and_then(OsStr::to_str)
+			.map_or_else(Vec::new, |extension| {
+				Self::by_extension(&extension.to_ascii_lowercase())
+			})
+	}
+
+	/// `extension` must be lowercase
+	fn by_extension(extension: &str) -> Vec<Self> {
+		match extension {
+			"avif" | "heic" | "heif" | "hif" => vec![
+				Codecs::from(Heif::default()),
+				Codecs::from(Generic::default()),
+			],
+			"jfif" | "jpe" | "jpeg" | "jpg" => vec![
+				Codecs::from(Jpeg::default()),
+				Codecs::from(Generic::default()),
+			],
+			_ if image::ImageFormat::from_extension(extension).is_some() => {
+				vec![Codecs::from(Generic::default())]
+			}
+			_ => Vec::new(),
 		}
 	}
 }
 
+fn save_exif_orientation(orientation: Orientation, output: &Path) -> Result<(), Error> {
+	LazyLock::force(&EXIV2_INIT);
+	let exiv = rexiv2::Metadata::new_from_path(output)?;
+
+	ensure!(exiv.supports_exif(), "Exif metadata is not supported");
+
+	exiv.set_orientation(orientation.into());
+	exiv.save_to_file(output)?;
+	Ok(())
+}
+
 impl fmt::Debug for Codecs {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		f.write_str(self.as_ref())
 	}
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Generic {}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Heif {}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct Jpeg {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const JPEG: [u8; 20] = [
+		0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0x01, 0x01, 0x00, 0x00,
+		0x01, 0x00, 0x01, 0x00, 0x00,
+	];
+
+	/// Start of an ISO base media file with a major brand, minor version and
+	/// compatible brands
+	fn ftyp(brands: &[u8]) -> Vec<u8> {
+		let mut file = u32::try_from(brands.len() + 8)
+			.unwrap()
+			.to_be_bytes()
+			.to_vec();
+
+		file.extend_from_slice(b"ftyp");
+		file.extend_from_slice(brands);
+		file.extend_from_slice(&[0x00; 32]);
+		file
+	}
+
+	fn heif() -> Vec<Codecs> {
+		vec![
+			Codecs::from(Heif::default()),
+			Codecs::from(Generic::default()),
+		]
+	}
+
+	fn jpeg() -> Vec<Codecs> {
+		vec![
+			Codecs::from(Jpeg::default()),
+			Codecs::from(Generic::default()),
+		]
+	}
+
+	fn generic() -> Vec<Codecs> {
+		vec![Codecs::from(Generic::default())]
+	}
+
+	#[test]
+	fn heif_by_content() {
+		assert_eq!(
+			Codecs::candidates(&ftyp(b"heic\0\0\0\0mif1heic"), Path::new("image")).unwrap(),
+			heif()
+		);
+	}
+
+	#[test]
+	fn heif_detected_as_mp4() {
+		let file = ftyp(b"mp42\0\0\0\0mp42isom");
+
+		assert_eq!(
+			Codecs::candidates(&file, Path::new("image.HEIC")).unwrap(),
+			heif()
+		);
+		assert!(Codecs::candidates(&file, Path::new("video.mp4")).is_err());
+	}
+
+	#[test]
+	fn jpeg_falls_back_to_generic() {
+		assert_eq!(
+			Codecs::candidates(&JPEG, Path::new("image.jpg")).unwrap(),
+			jpeg()
+		);
+
+		// The content takes priority over the extension
+		assert_eq!(
+			Codecs::candidates(&JPEG, Path::new("image.png")).unwrap(),
+			jpeg()
+		);
+		assert_eq!(
+			Codecs::candidates(&JPEG, Path::new("image.heic")).unwrap(),
+			[jpeg(), vec![Codecs::from(Heif::default())]].concat()
+		);
+	}
+
+	#[test]
+	fn unknown_types() {
+		let text = b"Hello, world\n";
+
+		assert!(Codecs::candidates(text, Path::new("file.txt")).is_err());
+		assert!(Codecs::candidates(text, Path::new("file")).is_err());
+		assert_eq!(
+			Codecs::candidates(text, Path::new("image.png")).unwrap(),
+			generic()
+		);
+	}
+
+	#[test]
+	fn by_extension() {
+		assert_eq!(Codecs::by_extension("avif"), heif());
+		assert_eq!(Codecs::by_extension("hif"), heif());
+		assert_eq!(Codecs::by_extension("jpe"), jpeg());
+		assert_eq!(Codecs::by_extension("png"), generic());
+		assert_eq!(Codecs::by_extension("txt"), Vec::new());
+
+		// Only `by_filename()` accepts any case
+		assert_eq!(Codecs::by_extension("JPG"), Vec::new());
+		assert_eq!(Codecs::by_filename(Path::new("image.JPG")), jpeg());
+		assert_eq!(Codecs::by_filename(Path::new("image")), Vec::new());
+	}
+}
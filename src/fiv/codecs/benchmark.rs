@@ -0,0 +1,158 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Decode sample images with the codecs that convert decoded pixels to XRGB
+// and compare them with decoding to RGB and then converting every pixel,
+// which is how they worked before.
+//
+// Run with:
+// FIV_BENCHMARK=a.jpg:b.png:c.heic cargo test --release benchmark -- --ignored --nocapture
+
+use super::{Cancel, Codec, Codecs};
+use crate::fiv::image::Pixel;
+use anyhow::{Error, anyhow};
+use image::{ColorType, DynamicImage, ImageDecoder, ImageReader};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+use std::{env, fs, hint::black_box};
+
+const RUNS: u32 = 5;
+
+#[test]
+#[ignore = "benchmark"]
+fn benchmark() {
+	let Some(filenames) = env::var_os("FIV_BENCHMARK") else {
+		println!("Set FIV_BENCHMARK to a list of sample images");
+		return;
+	};
+
+	for filename in env::split_paths(&filenames) {
+		let file = fs::read(&filename).unwrap();
+
+		for codec in Codecs::candidates(&file, &filename).unwrap() {
+			let baseline = match codec {
+				Codecs::Generic(_) => generic_rgb,
+				Codecs::Heif(_) => heif_rgb,
+				Codecs::Jpeg(_) => continue,
+			};
+
+			let current = time(|| {
+				let metadata = codec.metadata(&file)?;
+
+				codec.primary(&file, &metadata, metadata.dimensions, &Cancel::default())
+			});
+			let previous = time(|| baseline(&file));
+
+			println!(
+				"{}: {codec:?} RGB to XRGB: {}, XRGB: {}",
+				filename.display(),
+				format_time(previous),
+				format_time(current),
+			);
+
+			if matches!(codec, Codecs::Heif(_)) {
+				let rgb = time(|| heif_decode(&file, RgbChroma::Rgb).map(|_| ()));
+				let rgba = time(|| heif_decode(&file, RgbChroma::Rgba).map(|_| ()));
+
+				println!(
+					"{}: libheif RGB: {}, RGBA: {}",
+					filename.display(),
+					format_time(rgb),
+					format_time(rgba),
+				);
+			}
+		}
+	}
+}
+
+/// Average time of successful runs
+fn time<T>(mut function: impl FnMut() -> Result<T, Error>) -> Result<Duration, Error> {
+	let start = Instant::now();
+
+	for _ in 0..RUNS {
+		black_box(function()?);
+	}
+
+	Ok(start.elapsed() / RUNS)
+}
+
+fn format_time(time: Result<Duration, Error>) -> String {
+	match time {
+		Ok(time) => format!("{time:?}"),
+		Err(err) => format!("{err}"),
+	}
+}
+
+/// Decode to RGB and convert each pixel to XRGB
+fn generic_rgb(file: &[u8]) -> Result<Vec<Pixel>, Error> {
+	let decoder = ImageReader::new(Cursor::new(file))
+		.with_guessed_format()?
+		.into_decoder()?;
+	let (channels, samples) = match decoder.color_type() {
+		ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => {
+			let mut samples = vec![0; usize::try_from(decoder.total_bytes())?];
+			let channels = usize::from(decoder.color_type().channel_count());
+
+			decoder.read_image(&mut samples)?;
+			(channels, samples)
+		}
+		_ => (
+			3,
+			DynamicImage::from_decoder(decoder)?.into_rgb8().into_raw(),
+		),
+	};
+
+	Ok(samples
+		.chunks_exact(channels)
+		.map(|src| {
+			if channels < 3 {
+				(u32::from(src[0]) << 16) | (u32::from(src[0]) << 8) | u32::from(src[0])
+			} else {
+				(u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2])
+			}
+		})
+		.collect())
+}
+
+/// Decode to RGB and convert each row of pixels to XRGB
+fn heif_rgb(file: &[u8]) -> Result<Vec<Pixel>, Error> {
+	let image = heif_decode(file, RgbChroma::Rgb)?;
+	let plane = image
+		.planes()
+		.interleaved
+		.ok_or_else(|| anyhow!("No interleaved plane"))?;
+	let width = usize::try_from(plane.width)?;
+
+	Ok(plane
+		.data
+		.chunks_exact(plane.stride)
+		.flat_map(|row| {
+			row[..width * 3]
+				.chunks_exact(3)
+				.map(|src| (u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2]))
+		})
+		.collect())
+}
+
+fn heif_decode(file: &[u8], chroma: RgbChroma) -> Result<libheif_rs::Image, Error> {
+	let context = HeifContext::read_from_bytes(file)?;
+	let handle = context.primary_image_handle()?;
+
+	Ok(LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), None)?)
+}
@@ -16,11 +16,37 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::{Codec, CodecMetadata, CodecPrimary, Generic, ImageData};
-use crate::fiv::{image::Pixel, numeric::DimensionsU32};
-use anyhow::{Error, ensure};
-use image::{DynamicImage, ImageDecoder, ImageReader};
-use std::io::{BufReader, Cursor};
+use super::{Cancel, Codec, CodecMetadata, CodecPrimary, Generic, ImageData, Partial, swizzle};
+use crate::fiv::image::{ImageDataBuilder, Pixel};
+use crate::fiv::numeric::DimensionsU32;
+use anyhow::{Error, anyhow, ensure};
+use image::{ColorType, DynamicImage, ExtendedColorType, ImageDecoder, ImageError, ImageReader};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+
+/// Partly decoded images with more samples than this aren't decoded a second
+/// time to find the rows that were decoded, because that needs another buffer
+/// of the same size and takes as long as the first decode
+const RECHECK_BYTES: usize = 64 * 1024 * 1024;
+
+/// Decoders read the file incrementally, so stop them by failing the next
+/// read after the decode has been cancelled
+struct CancelReader<'a, R> {
+	inner: R,
+	cancel: &'a Cancel,
+}
+
+impl<R: Read> Read for CancelReader<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.cancel.check().map_err(io::Error::other)?;
+		self.inner.read(buf)
+	}
+}
+
+impl<R: Seek> Seek for CancelReader<'_, R> {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.inner.seek(pos)
+	}
+}
 
 impl Codec for Generic {
 	fn metadata(&self, file: &[u8]) -> Result<CodecMetadata, Error> {
@@ -28,18 +54,31 @@ impl Codec for Generic {
 			.with_guessed_format()?
 			.into_decoder()?;
 
+		let color_type = decoder.original_color_type();
+
 		Ok(CodecMetadata {
 			dimensions: decoder.dimensions().into(),
 			orientation: decoder.orientation().unwrap().into(),
 			af_points: None,
+			bit_depth: match color_type {
+				// Palette indexes
+				ExtendedColorType::Unknown(_) => None,
+				_ => u8::try_from(
+					color_type.bits_per_pixel() / u16::from(color_type.channel_count()),
+				)
+				.ok(),
+			},
 		})
 	}
 
-	fn primary(&self, file: &[u8], metadata: &CodecMetadata) -> Result<CodecPrimary, Error> {
-		let decoder = ImageReader::new(BufReader::new(Cursor::new(file)))
-			.with_guessed_format()?
-			.into_decoder()?;
-
+	fn primary(
+		&self,
+		file: &[u8],
+		metadata: &CodecMetadata,
+		_minimum: DimensionsU32,
+		cancel: &Cancel,
+	) -> Result<CodecPrimary, Error> {
+		let decoder = new_decoder(file, cancel)?;
 		let dimensions: DimensionsU32 = decoder.dimensions().into();
 
 		ensure!(
@@ -49,20 +88,145 @@ impl Codec for Generic {
 			metadata.dimensions,
 		);
 
-		let image = DynamicImage::from_decoder(decoder)?.into_rgb8();
-		let samples = image.as_flat_samples().samples;
-		let mut image_data = ImageData::builder(dimensions)?;
-
-		ensure!(AsRef::<[Pixel]>::as_ref(&image_data).len() * 3 == samples.len());
-
-		// Decoding images as RGB and then converting them to XBGR adds 33% to
-		// the total time compared to decoding to XBGR directly ☹️
-		for (src, dst) in samples.chunks_exact(3).zip(image_data.iter_mut()) {
-			*dst = (u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2]);
-		}
+		let (image_data, partial) = match decoder.color_type() {
+			ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => {
+				read_8bit(decoder, || new_decoder(file, cancel), dimensions, cancel)?
+			}
+			_ => (read_any(decoder, dimensions, cancel)?, None),
+		};
 
 		Ok(CodecPrimary {
 			image_data: image_data.into(),
+			partial,
 		})
 	}
 }
+
+fn new_decoder<'a>(file: &'a [u8], cancel: &'a Cancel) -> Result<impl ImageDecoder + 'a, Error> {
+	let reader = CancelReader {
+		inner: Cursor::new(file),
+		cancel,
+	};
+
+	Ok(ImageReader::new(BufReader::new(reader))
+		.with_guessed_format()?
+		.into_decoder()?)
+}
+
+/// Decoders write rows into the buffer as they're decoded, so if decoding
+/// fails part way through then the rows that have been decoded can be kept
+///
+/// The samples are decoded straight into the image data, which is large
+/// enough for them because there are never more than 4 channels, and then
+/// expanded to XRGB in place
+fn read_8bit<D: ImageDecoder>(
+	decoder: D,
+	again: impl FnOnce() -> Result<D, Error>,
+	dimensions: DimensionsU32,
+	cancel: &Cancel,
+) -> Result<(ImageDataBuilder, Option<Partial>), Error> {
+	let channels = usize::from(decoder.color_type().channel_count());
+	let length = usize::try_from(decoder.total_bytes())?;
+	let row_length = usize::try_from(u32::from(dimensions.width))? * channels;
+	let mut image_data = ImageData::builder(dimensions)?;
+
+	ensure!(AsRef::<[Pixel]>::as_ref(&image_data).len() * channels == length);
+
+	let warning = match decoder.read_image(&mut AsMut::<[u8]>::as_mut(&mut image_data)[..length]) {
+		Ok(()) => None,
+		Err(err) if cancel.cancelled() => return Err(err.into()),
+		Err(err @ (ImageError::Decoding(_) | ImageError::IoError(_))) => Some(err.to_string()),
+		Err(err) => return Err(err.into()),
+	};
+
+	cancel.check()?;
+
+	let rows = match &warning {
+		Some(_) => decoded_rows(
+			&AsRef::<[u8]>::as_ref(&image_data)[..length],
+			row_length,
+			again,
+		)?,
+		None => 0,
+	};
+
+	cancel.check()?;
+	swizzle::expand_in_place(image_data.as_mut(), channels);
+
+	let Some(warning) = warning else {
+		return Ok((image_data, None));
+	};
+
+	if rows == 0 {
+		return Err(anyhow!(warning));
+	}
+
+	Ok((
+		image_data,
+		Some(Partial {
+			rows: u32::try_from(rows).unwrap_or(u32::MAX).into(),
+			warning,
+		}),
+	))
+}
+
+/// Rows from the top of the image that the decoder wrote into `samples`
+/// (which was initialised to zero) before it failed
+///
+/// Decoders don't report how far they got. The rows up to the last row with a
+/// non-zero sample were decoded, and some of the zero rows after it may have
+/// been decoded too if they're black. To find out, the image is decoded again
+/// into a buffer initialised to 0xFF, because the samples that the decoder
+/// wrote are the same both times and the rest of them are different.
+///
+/// That doubles the time and memory used, so images larger than
+/// `RECHECK_BYTES` aren't decoded again and any black rows at the end of the
+/// decoded part are counted as missing.
+fn decoded_rows<D: ImageDecoder>(
+	samples: &[u8],
+	row_length: usize,
+	again: impl FnOnce() -> Result<D, Error>,
+) -> Result<usize, Error> {
+	let row_length = row_length.max(1);
+	let non_zero = samples
+		.chunks_exact(row_length)
+		.rposition(|row| row.iter().any(|&sample| sample != 0))
+		.map_or(0, |index| index + 1);
+
+	if samples.len() > RECHECK_BYTES || non_zero == samples.len() / row_length {
+		return Ok(non_zero);
+	}
+
+	let mut filled = vec![0xFF; samples.len()];
+	let _ = again()?.read_image(&mut filled);
+
+	Ok(non_zero
+		+ samples
+			.chunks_exact(row_length)
+			.zip(filled.chunks_exact(row_length))
+			.skip(non_zero)
+			.take_while(|(first, second)| first == second)
+			.count())
+}
+
+fn read_any(
+	decoder: impl ImageDecoder,
+	dimensions: DimensionsU32,
+	cancel: &Cancel,
+) -> Result<ImageDataBuilder, Error> {
+	let image = DynamicImage::from_decoder(decoder)?;
+
+	cancel.check()?;
+
+	// Other sample formats have to be reduced to 8 bits separately, which
+	// is slower but they're uncommon
+	let image = image.into_rgba8();
+	let samples = image.as_flat_samples().samples;
+	let mut image_data = ImageData::builder(dimensions)?;
+
+	ensure!(AsRef::<[Pixel]>::as_ref(&image_data).len() * 4 == samples.len());
+
+	swizzle::rgba_row(samples, image_data.as_mut());
+
+	Ok(image_data)
+}
@@ -16,10 +16,14 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::{Codec, CodecMetadata, CodecPrimary, Heif, ImageData};
+mod grid;
+
+use super::{Cancel, Codec, CodecMetadata, CodecPrimary, Heif, ImageData, swizzle};
+use crate::fiv::image::ImageDataBuilder;
 use crate::fiv::{Orientation, numeric::DimensionsU32};
-use anyhow::{Error, ensure};
-use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use anyhow::{Error, anyhow, ensure};
+use grid::Grid;
+use libheif_rs::{ColorSpace, HeifContext, ImageHandle, LibHeif, RgbChroma};
 use std::sync::LazyLock;
 
 impl From<&libheif_rs::ImageHandle> for DimensionsU32 {
@@ -44,10 +48,17 @@ impl Codec for Heif {
 			dimensions,
 			orientation,
 			af_points: None,
+			bit_depth: Some(handle.luma_bits_per_pixel()).filter(|&bits| bits > 0),
 		})
 	}
 
-	fn primary(&self, file: &[u8], metadata: &CodecMetadata) -> Result<CodecPrimary, Error> {
+	fn primary(
+		&self,
+		file: &[u8],
+		metadata: &CodecMetadata,
+		_minimum: DimensionsU32,
+		cancel: &Cancel,
+	) -> Result<CodecPrimary, Error> {
 		let context = HeifContext::read_from_bytes(file)?;
 		let handle = context.primary_image_handle()?;
 		let dimensions = DimensionsU32::from(&handle);
@@ -59,25 +70,137 @@ impl Codec for Heif {
 			metadata.dimensions,
 		);
 
+		// More than 8 bits per channel are decoded as 16-bit samples instead of
+		// having libheif reduce them to 8 bits in a separate pass
+		let hdr = handle.luma_bits_per_pixel() > 8;
+		let color_space = ColorSpace::Rgb(if hdr {
+			RgbChroma::HdrRgbLe
+		} else {
+			RgbChroma::Rgba
+		});
 		let mut image_data = ImageData::builder(dimensions)?;
-		let image = LIB_HEIF.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)?;
-		let plane = image.planes().interleaved.unwrap();
-		let pixel_stride = usize::try_from(i32::from(image_data.width)).unwrap();
-
-		// Decoding images as RGB and then converting them to XBGR wastes time
-		// compared to decoding to XBGR directly ☹️ (and libheif's stride varies)
-		for (src_row, dst_row) in plane
-			.data
-			.chunks_exact(plane.stride)
-			.zip(AsMut::<[u32]>::as_mut(&mut image_data).chunks_exact_mut(pixel_stride))
+
+		if let Some(grid) = Grid::find(file, handle.item_id())
+			.filter(|grid| tiles_match(&context, &handle, grid, dimensions))
 		{
-			for (src, dst) in src_row.chunks_exact(3).zip(dst_row.iter_mut()) {
-				*dst = (u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2]);
-			}
+			decode_tiles(&context, &grid, color_space, &mut image_data, cancel)?;
+		} else {
+			// libheif has no way to interrupt a decode of the whole image
+			cancel.check()?;
+
+			let image = LIB_HEIF.decode(&handle, color_space, None)?;
+
+			paste(&image, (0, 0), &mut image_data)?;
 		}
 
 		Ok(CodecPrimary {
 			image_data: image_data.into(),
+			partial: None,
 		})
 	}
 }
+
+/// The tiles can only be decoded individually if they'll produce the same
+/// result as decoding the whole grid, which needs the same colour conversion
+fn tiles_match(
+	context: &HeifContext,
+	handle: &ImageHandle,
+	grid: &Grid,
+	dimensions: DimensionsU32,
+) -> bool {
+	let Some(tile) = grid
+		.tiles
+		.first()
+		.and_then(|&id| context.image_handle(id).ok())
+	else {
+		return false;
+	};
+	let nclx = |image: &ImageHandle| {
+		image.color_profile_nclx().map(|nclx| {
+			(
+				nclx.color_primaries(),
+				nclx.transfer_characteristics(),
+				nclx.matrix_coefficients(),
+				nclx.full_range_flag(),
+			)
+		})
+	};
+
+	grid.dimensions == dimensions
+		&& u64::from(tile.width()) * u64::from(grid.columns)
+			>= u64::from(u32::from(dimensions.width))
+		&& u64::from(tile.height()) * u64::from(grid.rows)
+			>= u64::from(u32::from(dimensions.height))
+		&& tile.luma_bits_per_pixel() == handle.luma_bits_per_pixel()
+		&& nclx(&tile) == nclx(handle)
+}
+
+/// Decode the tiles of a grid one at a time, checking for cancellation
+/// between each tile
+///
+/// This already runs on a preload thread so the tiles aren't decoded in
+/// parallel, which would use too many threads when several images are being
+/// decoded at the same time
+fn decode_tiles(
+	context: &HeifContext,
+	grid: &Grid,
+	color_space: ColorSpace,
+	image_data: &mut ImageDataBuilder,
+	cancel: &Cancel,
+) -> Result<(), Error> {
+	let columns = usize::try_from(grid.columns)?;
+
+	for (index, &id) in grid.tiles.iter().enumerate() {
+		cancel.check()?;
+
+		let image = LIB_HEIF.decode(&context.image_handle(id)?, color_space, None)?;
+		let plane = image
+			.planes()
+			.interleaved
+			.ok_or_else(|| anyhow!("No interleaved plane"))?;
+		let x = usize::try_from(plane.width)? * (index % columns);
+		let y = usize::try_from(plane.height)? * (index / columns);
+
+		paste(&image, (x, y), image_data)?;
+	}
+
+	Ok(())
+}
+
+/// Copy a decoded image into the image data at a position, swapping the
+/// channels and cropping it to fit
+///
+/// libheif can't decode into another buffer and its stride varies, so the
+/// rows are copied one at a time
+fn paste(
+	image: &libheif_rs::Image,
+	(x, y): (usize, usize),
+	image_data: &mut ImageDataBuilder,
+) -> Result<(), Error> {
+	let plane = image
+		.planes()
+		.interleaved
+		.ok_or_else(|| anyhow!("No interleaved plane"))?;
+	let hdr = image.color_space() == Some(ColorSpace::Rgb(RgbChroma::HdrRgbLe));
+	let width = usize::try_from(i32::from(image_data.width))?;
+	let height = usize::try_from(i32::from(image_data.height))?;
+	let columns = usize::try_from(plane.width)?.min(width.saturating_sub(x));
+	let pixels: &mut [u32] = image_data.as_mut();
+
+	for (src_row, dst_row) in plane.data.chunks(plane.stride).zip(
+		pixels
+			.chunks_exact_mut(width)
+			.skip(y)
+			.take(height.saturating_sub(y)),
+	) {
+		let dst_row = &mut dst_row[x..x + columns];
+
+		if hdr {
+			swizzle::hdr_rgb_row(src_row, dst_row, plane.bits_per_pixel);
+		} else {
+			swizzle::rgba_row(src_row, dst_row);
+		}
+	}
+
+	Ok(())
+}
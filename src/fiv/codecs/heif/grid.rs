@@ -0,0 +1,708 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Large HEIF images are usually a grid of tiles that are separate images.
+// libheif decodes all of the tiles at once with no way to interrupt it, but
+// it can decode the tiles individually if they can be found in the file.
+// Only the boxes needed to find them are read (ISO/IEC 14496-12 and 23008-12).
+
+use crate::fiv::numeric::DimensionsU32;
+
+/// Properties that transform the image after the tiles have been combined
+const TRANSFORMATIONS: [&[u8; 4]; 3] = [b"clap", b"irot", b"imir"];
+
+#[derive(Debug)]
+pub struct Grid {
+	pub rows: u32,
+	pub columns: u32,
+	pub dimensions: DimensionsU32,
+
+	/// Item IDs of the tiles, a row at a time
+	pub tiles: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+#[derive(Debug)]
+struct Meta<'a> {
+	iinf: &'a [u8],
+	iloc: &'a [u8],
+	iref: &'a [u8],
+	iprp: &'a [u8],
+	idat: &'a [u8],
+}
+
+impl Grid {
+	/// Returns `None` if the item isn't a grid, or if it has transformations
+	/// that would have to be applied after combining the tiles
+	pub fn find(file: &[u8], item_id: u32) -> Option<Self> {
+		let meta = Meta::find(file)?;
+
+		if meta.item_type(item_id)? != *b"grid" || meta.transformed(item_id)? {
+			return None;
+		}
+
+		let data = meta.item_data(file, item_id)?;
+		let mut data = Reader::new(&data);
+		let (_version, flags) = (data.u8()?, data.u8()?);
+		let rows = u32::from(data.u8()?) + 1;
+		let columns = u32::from(data.u8()?) + 1;
+		let (width, height) = if flags & 1 == 0 {
+			(u32::from(data.u16()?), u32::from(data.u16()?))
+		} else {
+			(data.u32()?, data.u32()?)
+		};
+		let tiles = meta.references(*b"dimg", item_id)?;
+
+		if u64::try_from(tiles.len()).ok()? != u64::from(rows) * u64::from(columns) {
+			return None;
+		}
+
+		Some(Self {
+			rows,
+			columns,
+			dimensions: DimensionsU32::new(width.into(), height.into()),
+			tiles,
+		})
+	}
+}
+
+impl<'a> Meta<'a> {
+	fn find(file: &'a [u8]) -> Option<Self> {
+		let (_, meta) = boxes(file).find(|(kind, _)| kind == b"meta")?;
+		let mut result = Self {
+			iinf: &[],
+			iloc: &[],
+			iref: &[],
+			iprp: &[],
+			idat: &[],
+		};
+
+		// Full box with a version and flags
+		for (kind, data) in boxes(meta.get(4..)?) {
+			match &kind {
+				b"iinf" => result.iinf = data,
+				b"iloc" => result.iloc = data,
+				b"iref" => result.iref = data,
+				b"iprp" => result.iprp = data,
+				b"idat" => result.idat = data,
+				_ => (),
+			}
+		}
+
+		Some(result)
+	}
+
+	fn item_type(&self, item_id: u32) -> Option<[u8; 4]> {
+		let mut iinf = Reader::new(self.iinf);
+		if iinf.full_box()? == 0 {
+			iinf.u16()?;
+		} else {
+			iinf.u32()?;
+		}
+
+		boxes(iinf.data)
+			.filter(|(kind, _)| kind == b"infe")
+			.find_map(|(_, infe)| {
+				let mut infe = Reader::new(infe);
+				let id = match infe.full_box()? {
+					2 => u32::from(infe.u16()?),
+					3 => infe.u32()?,
+					_ => return None,
+				};
+
+				infe.u16()?;
+				(id == item_id).then(|| infe.fourcc()).flatten()
+			})
+	}
+
+	/// Items that an item refers to with a type of reference, in order
+	fn references(&self, kind: [u8; 4], item_id: u32) -> Option<Vec<u32>> {
+		let mut iref = Reader::new(self.iref);
+		let large = iref.full_box()? != 0;
+		let id = |reader: &mut Reader| {
+			if large {
+				reader.u32()
+			} else {
+				reader.u16().map(u32::from)
+			}
+		};
+
+		boxes(iref.data)
+			.filter(|(other, _)| *other == kind)
+			.find_map(|(_, data)| {
+				let mut data = Reader::new(data);
+
+				if id(&mut data)? != item_id {
+					return None;
+				}
+
+				(0..data.u16()?).map(|_| id(&mut data)).collect()
+			})
+	}
+
+	/// Returns true if the item has any transformation properties
+	fn transformed(&self, item_id: u32) -> Option<bool> {
+		let ipco: Vec<[u8; 4]> = boxes(self.iprp)
+			.find(|(kind, _)| kind == b"ipco")
+			.map(|(_, ipco)| boxes(ipco).map(|(kind, _)| kind).collect())?;
+		let mut transformed = false;
+
+		for (_, ipma) in boxes(self.iprp).filter(|(kind, _)| kind == b"ipma") {
+			let mut ipma = Reader::new(ipma);
+			let version = ipma.u8()?;
+			let flags = ipma.u24()?;
+
+			for _ in 0..ipma.u32()? {
+				let id = if version < 1 {
+					u32::from(ipma.u16()?)
+				} else {
+					ipma.u32()?
+				};
+
+				for _ in 0..ipma.u8()? {
+					// The highest bit is the "essential" flag
+					let index = if flags & 1 == 0 {
+						usize::from(ipma.u8()? & 0x7F)
+					} else {
+						usize::from(ipma.u16()? & 0x7FFF)
+					};
+
+					if id == item_id
+						&& let Some(kind) = index.checked_sub(1).and_then(|index| ipco.get(index))
+						&& TRANSFORMATIONS.contains(&kind)
+					{
+						transformed = true;
+					}
+				}
+			}
+		}
+
+		Some(transformed)
+	}
+
+	/// Contents of an item, from the file or from the "idat" box
+	fn item_data(&self, file: &'a [u8], item_id: u32) -> Option<Vec<u8>> {
+		let mut iloc = Reader::new(self.iloc);
+		let version = iloc.full_box()?;
+		let [offset_size, length_size] = iloc.nibbles()?;
+		let [base_offset_size, index_size] = iloc.nibbles()?;
+		let count = if version < 2 {
+			u32::from(iloc.u16()?)
+		} else {
+			iloc.u32()?
+		};
+
+		for _ in 0..count {
+			let id = if version < 2 {
+				u32::from(iloc.u16()?)
+			} else {
+				iloc.u32()?
+			};
+			let construction = if version > 0 { iloc.u16()? & 0xF } else { 0 };
+
+			iloc.u16()?;
+
+			let base = iloc.sized(base_offset_size)?;
+			let mut data = Vec::new();
+
+			for _ in 0..iloc.u16()? {
+				if version > 0 {
+					iloc.sized(index_size)?;
+				}
+
+				let offset = usize::try_from(base.checked_add(iloc.sized(offset_size)?)?).ok()?;
+				let length = usize::try_from(iloc.sized(length_size)?).ok()?;
+
+				if id != item_id {
+					continue;
+				}
+
+				let source = match construction {
+					0 => file,
+					1 => self.idat,
+					_ => return None,
+				};
+
+				data.extend_from_slice(if length == 0 {
+					source.get(offset..)?
+				} else {
+					source.get(offset..offset.checked_add(length)?)?
+				});
+			}
+
+			if id == item_id {
+				return Some(data);
+			}
+		}
+
+		None
+	}
+}
+
+impl<'a> Reader<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		Self { data }
+	}
+
+	fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+		let (bytes, rest) = self.data.split_first_chunk::<N>()?;
+
+		self.data = rest;
+		Some(*bytes)
+	}
+
+	fn u8(&mut self) -> Option<u8> {
+		self.take::<1>().map(|[byte]| byte)
+	}
+
+	fn nibbles(&mut self) -> Option<[u8; 2]> {
+		self.u8().map(|byte| [byte >> 4, byte & 0xF])
+	}
+
+	fn u16(&mut self) -> Option<u16> {
+		self.take().map(u16::from_be_bytes)
+	}
+
+	fn u24(&mut self) -> Option<u32> {
+		self.take::<3>()
+			.map(|[b0, b1, b2]| u32::from_be_bytes([0, b0, b1, b2]))
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		self.take().map(u32::from_be_bytes)
+	}
+
+	fn u64(&mut self) -> Option<u64> {
+		self.take().map(u64::from_be_bytes)
+	}
+
+	fn fourcc(&mut self) -> Option<[u8; 4]> {
+		self.take()
+	}
+
+	/// Value with a size in bytes that is specified in the file
+	fn sized(&mut self, size: u8) -> Option<u64> {
+		match size {
+			0 => Some(0),
+			4 => self.u32().map(u64::from),
+			8 => self.u64(),
+			_ => None,
+		}
+	}
+
+	/// Returns the version of a full box, skipping the flags
+	fn full_box(&mut self) -> Option<u8> {
+		let version = self.u8()?;
+
+		self.u24()?;
+		Some(version)
+	}
+}
+
+/// Type and contents of each box
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+	let mut reader = Reader::new(data);
+
+	std::iter::from_fn(move || {
+		let size = reader.u32()?;
+		let kind = reader.fourcc()?;
+		let header = if size == 1 { 16 } else { 8 };
+		let size = match size {
+			0 => reader.data.len() + header,
+			1 => usize::try_from(reader.u64()?).ok()?,
+			size => usize::try_from(size).ok()?,
+		};
+		let (contents, rest) = reader.data.split_at_checked(size.checked_sub(header)?)?;
+
+		reader.data = rest;
+		Some((kind, contents))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const GRID_ID: u32 = 1;
+
+	/// Contents of the boxes in a file with a grid item
+	struct Fixture {
+		/// Use 32-bit item IDs in "iinf", "iref" and "ipma"
+		large: bool,
+		iloc_version: u8,
+		/// Store the grid item in the "idat" box instead of "mdat"
+		idat: bool,
+		item_type: &'static str,
+		rows: u32,
+		columns: u32,
+		width: u32,
+		height: u32,
+		tiles: Vec<u32>,
+		/// Properties associated with the grid item
+		properties: Vec<&'static str>,
+	}
+
+	impl Fixture {
+		fn new(iloc_version: u8) -> Self {
+			Self {
+				large: false,
+				iloc_version,
+				idat: false,
+				item_type: "grid",
+				rows: 2,
+				columns: 3,
+				width: 1000,
+				height: 600,
+				tiles: (2..8).collect(),
+				properties: vec!["ispe", "pixi"],
+			}
+		}
+
+		fn file(&self) -> Vec<u8> {
+			let ftyp = bmff("ftyp", b"heic\0\0\0\0mif1heic");
+			let grid = self.grid();
+			let mut file = Vec::new();
+
+			// The offset of the grid in "mdat" depends on the size of "meta",
+			// which doesn't depend on the offset
+			for _ in 0..2 {
+				let offset = ftyp.len() + self.meta(0).len() + 8;
+
+				file.clear();
+				file.extend_from_slice(&ftyp);
+				file.extend_from_slice(&self.meta(u64::try_from(offset).unwrap()));
+				file.extend_from_slice(&bmff("mdat", if self.idat { &[] } else { &grid }));
+			}
+
+			file
+		}
+
+		fn grid(&self) -> Vec<u8> {
+			let large = self.width > 0xFFFF || self.height > 0xFFFF;
+			let mut data = vec![
+				0,
+				u8::from(large),
+				u8::try_from(self.rows - 1).unwrap(),
+				u8::try_from(self.columns - 1).unwrap(),
+			];
+
+			if large {
+				data.extend_from_slice(&self.width.to_be_bytes());
+				data.extend_from_slice(&self.height.to_be_bytes());
+			} else {
+				data.extend_from_slice(&u16::try_from(self.width).unwrap().to_be_bytes());
+				data.extend_from_slice(&u16::try_from(self.height).unwrap().to_be_bytes());
+			}
+
+			data
+		}
+
+		fn meta(&self, offset: u64) -> Vec<u8> {
+			let mut meta = full_box("hdlr", 0, 0, b"\0\0\0\0pict\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+			meta.extend_from_slice(&self.iinf());
+			meta.extend_from_slice(&self.iref());
+			meta.extend_from_slice(&self.iprp());
+			meta.extend_from_slice(&self.iloc(offset));
+
+			if self.idat {
+				// The grid is after some other data
+				let mut idat = vec![0xFF; 3];
+
+				idat.extend_from_slice(&self.grid());
+				meta.extend_from_slice(&bmff("idat", &idat));
+			}
+
+			full_box("meta", 0, 0, &meta)
+		}
+
+		fn iinf(&self) -> Vec<u8> {
+			let mut iinf = id_bytes(self.large, u32::try_from(self.tiles.len() + 1).unwrap());
+
+			for (id, item_type) in std::iter::once((GRID_ID, self.item_type))
+				.chain(self.tiles.iter().map(|&id| (id, "hvc1")))
+			{
+				let mut infe = id_bytes(self.large, id);
+
+				infe.extend_from_slice(&[0, 0]);
+				infe.extend_from_slice(item_type.as_bytes());
+				infe.push(0);
+				iinf.extend_from_slice(&full_box("infe", if self.large { 3 } else { 2 }, 0, &infe));
+			}
+
+			full_box("iinf", u8::from(self.large), 0, &iinf)
+		}
+
+		fn iref(&self) -> Vec<u8> {
+			// A reference of another type from the grid comes first
+			let mut thmb = id_bytes(self.large, GRID_ID);
+			let mut dimg = id_bytes(self.large, GRID_ID);
+
+			thmb.extend_from_slice(&1u16.to_be_bytes());
+			thmb.extend_from_slice(&id_bytes(self.large, 100));
+
+			dimg.extend_from_slice(&u16::try_from(self.tiles.len()).unwrap().to_be_bytes());
+			for &tile in &self.tiles {
+				dimg.extend_from_slice(&id_bytes(self.large, tile));
+			}
+
+			let mut iref = bmff("thmb", &thmb);
+
+			iref.extend_from_slice(&bmff("dimg", &dimg));
+			full_box("iref", u8::from(self.large), 0, &iref)
+		}
+
+		fn iprp(&self) -> Vec<u8> {
+			let mut ipco = Vec::new();
+			let mut ipma = 2u32.to_be_bytes().to_vec();
+			let mut tile = id_bytes(self.large, self.tiles[0]);
+			let mut grid = id_bytes(self.large, GRID_ID);
+
+			// The tiles have their own transformation, which doesn't matter
+			ipco.extend_from_slice(&bmff("imir", &[0]));
+			tile.push(1);
+			tile.extend_from_slice(&self.index(1));
+
+			grid.push(u8::try_from(self.properties.len()).unwrap());
+			for (index, property) in self.properties.iter().enumerate() {
+				ipco.extend_from_slice(&bmff(property, &[]));
+				grid.extend_from_slice(&self.index(index + 2));
+			}
+
+			ipma.extend_from_slice(&tile);
+			ipma.extend_from_slice(&grid);
+
+			let mut iprp = bmff("ipco", &ipco);
+
+			iprp.extend_from_slice(&full_box(
+				"ipma",
+				u8::from(self.large),
+				u32::from(self.large),
+				&ipma,
+			));
+			bmff("iprp", &iprp)
+		}
+
+		/// Property index with the "essential" flag set
+		fn index(&self, index: usize) -> Vec<u8> {
+			if self.large {
+				(u16::try_from(index).unwrap() | 0x8000)
+					.to_be_bytes()
+					.to_vec()
+			} else {
+				vec![u8::try_from(index).unwrap() | 0x80]
+			}
+		}
+
+		fn iloc(&self, offset: u64) -> Vec<u8> {
+			let version = self.iloc_version;
+			let (offset_size, length_size, base_offset_size, index_size) = match version {
+				0 => (4, 4, 0, 0),
+				1 => (4, 4, 4, 4),
+				_ => (8, 8, 8, 0),
+			};
+			let count = u32::try_from(self.tiles.len() + 1).unwrap();
+			let mut iloc = vec![
+				offset_size << 4 | length_size,
+				base_offset_size << 4 | index_size,
+			];
+			let sized = |data: &mut Vec<u8>, size: u8, value: u64| {
+				data.extend_from_slice(&value.to_be_bytes()[8 - usize::from(size)..]);
+			};
+
+			iloc.extend_from_slice(&id_bytes(version >= 2, count));
+
+			let mut entry = |id: u32, construction: u16, base: u64, extent: (u64, u64)| {
+				iloc.extend_from_slice(&id_bytes(version >= 2, id));
+
+				if version > 0 {
+					iloc.extend_from_slice(&construction.to_be_bytes());
+				}
+
+				iloc.extend_from_slice(&[0, 0]);
+				sized(&mut iloc, base_offset_size, base);
+				iloc.extend_from_slice(&1u16.to_be_bytes());
+
+				if version > 0 {
+					sized(&mut iloc, index_size, 0);
+				}
+
+				sized(&mut iloc, offset_size, extent.0);
+				sized(&mut iloc, length_size, extent.1);
+			};
+
+			// The tiles are listed before the grid, with no data
+			for &tile in &self.tiles {
+				entry(tile, 0, 0, (0, 0));
+			}
+
+			let length = u64::try_from(self.grid().len()).unwrap();
+			let base = if base_offset_size > 0 { 2 } else { 0 };
+
+			if self.idat {
+				entry(GRID_ID, 1, base, (3 - base, length));
+			} else {
+				entry(GRID_ID, 0, base, (offset.saturating_sub(base), length));
+			}
+
+			full_box("iloc", version, 0, &iloc)
+		}
+	}
+
+	fn id_bytes(large: bool, id: u32) -> Vec<u8> {
+		if large {
+			id.to_be_bytes().to_vec()
+		} else {
+			u16::try_from(id).unwrap().to_be_bytes().to_vec()
+		}
+	}
+
+	/// Box with a type and contents
+	fn bmff(kind: &str, contents: &[u8]) -> Vec<u8> {
+		let mut data = u32::try_from(contents.len() + 8)
+			.unwrap()
+			.to_be_bytes()
+			.to_vec();
+
+		data.extend_from_slice(kind.as_bytes());
+		data.extend_from_slice(contents);
+		data
+	}
+
+	fn full_box(kind: &str, version: u8, flags: u32, contents: &[u8]) -> Vec<u8> {
+		let mut data = flags.to_be_bytes();
+
+		data[0] = version;
+		bmff(kind, &[&data[..], contents].concat())
+	}
+
+	fn assert_grid(fixture: &Fixture) {
+		let grid = Grid::find(&fixture.file(), GRID_ID).unwrap();
+
+		assert_eq!(grid.rows, fixture.rows);
+		assert_eq!(grid.columns, fixture.columns);
+		assert_eq!(
+			grid.dimensions,
+			DimensionsU32::new(fixture.width.into(), fixture.height.into())
+		);
+		assert_eq!(grid.tiles, fixture.tiles);
+	}
+
+	#[test]
+	fn iloc_v0() {
+		assert_grid(&Fixture::new(0));
+	}
+
+	#[test]
+	fn iloc_v1() {
+		assert_grid(&Fixture::new(1));
+	}
+
+	#[test]
+	fn iloc_v2() {
+		assert_grid(&Fixture::new(2));
+	}
+
+	#[test]
+	fn idat() {
+		for version in 1..=2 {
+			assert_grid(&Fixture {
+				idat: true,
+				..Fixture::new(version)
+			});
+		}
+	}
+
+	#[test]
+	fn large() {
+		for version in 0..=2 {
+			assert_grid(&Fixture {
+				large: true,
+				rows: 10,
+				columns: 12,
+				width: 100_000,
+				height: 70_000,
+				// Item IDs in "iloc" are only 32-bit from version 2
+				tiles: if version >= 2 {
+					(0x1_0000..0x1_0000 + 120).collect()
+				} else {
+					(2..122).collect()
+				},
+				..Fixture::new(version)
+			});
+		}
+	}
+
+	#[test]
+	fn not_grid() {
+		let file = Fixture {
+			item_type: "hvc1",
+			..Fixture::new(0)
+		}
+		.file();
+
+		assert!(Grid::find(&file, GRID_ID).is_none());
+		assert!(Grid::find(&Fixture::new(0).file(), GRID_ID + 1).is_none());
+	}
+
+	#[test]
+	fn transformed() {
+		for property in TRANSFORMATIONS {
+			let file = Fixture {
+				properties: vec!["ispe", str::from_utf8(property).unwrap()],
+				..Fixture::new(1)
+			}
+			.file();
+
+			assert!(Grid::find(&file, GRID_ID).is_none());
+		}
+	}
+
+	#[test]
+	fn missing_tiles() {
+		let file = Fixture {
+			tiles: (2..7).collect(),
+			..Fixture::new(0)
+		}
+		.file();
+
+		assert!(Grid::find(&file, GRID_ID).is_none());
+	}
+
+	#[test]
+	fn truncated() {
+		for idat in [false, true] {
+			let file = Fixture {
+				idat,
+				..Fixture::new(1)
+			}
+			.file();
+
+			// Without the "mdat" box, which is empty when the grid is in "idat"
+			let end = file.len() - if idat { 8 } else { 0 };
+
+			for length in 0..end {
+				assert!(Grid::find(&file[..length], GRID_ID).is_none());
+			}
+		}
+	}
+}
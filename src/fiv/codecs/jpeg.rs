@@ -16,14 +16,37 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::{Codec, CodecMetadata, CodecPrimary, ImageData, Jpeg};
+use super::{Cancel, Codec, CodecMetadata, CodecPrimary, ImageData, Jpeg, Partial};
 use crate::fiv::{
-	AFPoint, ByteOrder, Orientation, byte_order_of,
+	AFPoint, ByteOrder, Orientation, Rotate, byte_order_of,
 	numeric::{DimensionsF64, DimensionsU32, PointF64, Xf64, Xu32, Yf64, Yu32},
 };
 use anyhow::{Error, anyhow, ensure};
 use bitfield::Bit;
+use std::ffi::{CStr, c_int};
+use std::fs;
+use std::path::Path;
+use std::ptr::NonNull;
 use std::sync::LazyLock;
+use turbojpeg::raw;
+
+/// Images with more than this many pixels in the file are decoded in equal
+/// bands of rows with no more than this many pixels, so that the decode can be
+/// cancelled part way through
+///
+/// Every band has to read the compressed data for all of the rows above it
+/// again. A 100 MP image takes 33-43% longer to decode in 2 bands and 75-150%
+/// longer in 4 bands, and a 300 MP image takes 21% longer in 2 bands, so only
+/// very large images are split.
+const BAND_PIXELS: u64 = 256 * 1024 * 1024;
+
+/// Bands start on a multiple of the largest iMCU height (scaled), which is a
+/// multiple of all the other iMCU heights
+const MCU_HEIGHT: usize = 32;
+
+/// The `turbojpeg` crate has no way to distinguish warnings from errors, so
+/// decompression uses the C API directly
+struct Decompressor(NonNull<std::ffi::c_void>);
 
 impl TryFrom<&turbojpeg::DecompressHeader> for DimensionsU32 {
 	type Error = Error;
@@ -49,13 +72,26 @@ impl Codec for Jpeg {
 			dimensions,
 			orientation,
 			af_points,
+			bit_depth: Some(8),
 		})
 	}
 
-	fn primary(&self, file: &[u8], metadata: &CodecMetadata) -> Result<CodecPrimary, Error> {
-		let mut decompressor = turbojpeg::Decompressor::new()?;
-		let header = decompressor.read_header(file)?;
-		let dimensions = DimensionsU32::try_from(&header)?;
+	fn decode_dimensions(&self, metadata: &CodecMetadata, minimum: DimensionsU32) -> DimensionsU32 {
+		scale(
+			metadata.dimensions,
+			scaling_factor(metadata.dimensions, minimum),
+		)
+	}
+
+	fn primary(
+		&self,
+		file: &[u8],
+		metadata: &CodecMetadata,
+		minimum: DimensionsU32,
+		cancel: &Cancel,
+	) -> Result<CodecPrimary, Error> {
+		let decompressor = Decompressor::new()?;
+		let dimensions = decompressor.read_header(file)?;
 
 		ensure!(
 			dimensions == metadata.dimensions,
@@ -64,26 +100,309 @@ impl Codec for Jpeg {
 			metadata.dimensions,
 		);
 
-		let mut image_data = ImageData::builder(dimensions)?;
-		let pitch = usize::try_from(image_data.stride)?;
-		let mut image = turbojpeg::Image {
-			pixels: image_data.as_mut(),
-			width: header.width,
-			pitch,
-			height: header.height,
-			format: if cfg!(target_endian = "little") {
-				turbojpeg::PixelFormat::BGRX
-			} else {
-				turbojpeg::PixelFormat::XRGB
-			},
+		let factor = scaling_factor(dimensions, minimum);
+
+		decompressor.set_scaling_factor(factor)?;
+
+		let mut image_data = ImageData::builder(scale(dimensions, factor))?;
+		let pitch = image_data.stride;
+		let format = if cfg!(target_endian = "little") {
+			turbojpeg::PixelFormat::BGRX
+		} else {
+			turbojpeg::PixelFormat::XRGB
+		};
+
+		let height = usize::try_from(i32::from(image_data.height))?;
+		let stride = usize::try_from(pitch)?;
+		let band = band_rows(&decompressor, dimensions, factor)?;
+		let mcu_rows = factor.scale(MCU_HEIGHT).max(1);
+		let mut warning = None;
+		let mut decode = |top: usize, rows: usize| {
+			let pixels =
+				&mut AsMut::<[u8]>::as_mut(&mut image_data)[top * stride..][..rows * stride];
+
+			if rows < height {
+				decompressor.set_cropping_region(top, rows)?;
+			}
+
+			decompressor.decompress(file, pixels, pitch, format)
 		};
 
-		decompressor.decompress(file, image.as_deref_mut())?;
+		// The TurboJPEG API has no way to interrupt a decode, so very large
+		// images are decoded in bands with an opportunity to stop between them
+		let band_height = band.unwrap_or(height).clamp(1, height.max(1));
+
+		for top in (0..height).step_by(band_height) {
+			let rows = band_height.min(height - top);
+
+			cancel.check()?;
+
+			if let Some(band_warning) = decode(top, rows)?
+				&& warning.is_none()
+			{
+				warning = Some((band_warning, top, rows));
+			}
+		}
+
+		let partial = match warning {
+			Some((warning, top, rows)) => {
+				// Images that can't be decoded in bands are corrupt rather
+				// than truncated, because the whole image is decoded
+				let decoded = if band.is_some() {
+					decoded_rows(&mut decode, top, rows, mcu_rows, cancel)?.unwrap_or(height)
+				} else {
+					height
+				};
+
+				Some(Partial {
+					rows: u32::try_from(decoded).unwrap_or(u32::MAX).into(),
+					warning,
+				})
+			}
+			None => None,
+		};
 
 		Ok(CodecPrimary {
 			image_data: image_data.into(),
+			partial,
 		})
 	}
+
+	fn save_orientation(
+		&self,
+		file: &[u8],
+		orientation: Orientation,
+		output: &Path,
+	) -> Result<(), Error> {
+		LazyLock::force(&super::EXIV2_INIT);
+
+		if rexiv2::Metadata::new_from_buffer(file).is_ok_and(|exiv| exiv.has_exif()) {
+			return super::save_exif_orientation(orientation, output);
+		}
+
+		// Without Exif metadata the image data itself needs to be transformed,
+		// the image is flipped before it is rotated
+		let mut transform =
+			turbojpeg::Transform::op(match (orientation.rotate, orientation.horizontal_flip) {
+				(Rotate::Rotate0, false) => turbojpeg::TransformOp::None,
+				(Rotate::Rotate90, false) => turbojpeg::TransformOp::Rot90,
+				(Rotate::Rotate180, false) => turbojpeg::TransformOp::Rot180,
+				(Rotate::Rotate270, false) => turbojpeg::TransformOp::Rot270,
+				(Rotate::Rotate0, true) => turbojpeg::TransformOp::Hflip,
+				(Rotate::Rotate90, true) => turbojpeg::TransformOp::Transverse,
+				(Rotate::Rotate180, true) => turbojpeg::TransformOp::Vflip,
+				(Rotate::Rotate270, true) => turbojpeg::TransformOp::Transpose,
+			});
+
+		// Don't modify or discard partial MCU blocks on the edges of the image
+		transform.perfect = true;
+
+		fs::write(output, turbojpeg::transform(&transform, file)?)?;
+		Ok(())
+	}
+}
+
+impl Decompressor {
+	fn new() -> Result<Self, Error> {
+		let handle = unsafe { raw::tj3Init(c_int::try_from(raw::TJINIT_TJINIT_DECOMPRESS)?) };
+		let decompressor =
+			Self(NonNull::new(handle).ok_or_else(|| anyhow!("Unable to initialise TurboJPEG"))?);
+
+		// Continue after warnings so that truncated and corrupt images are
+		// partially decoded
+		decompressor.set(raw::TJPARAM_TJPARAM_STOPONWARNING, 0)?;
+		Ok(decompressor)
+	}
+
+	fn error(&self) -> Error {
+		let message = unsafe { CStr::from_ptr(raw::tj3GetErrorStr(self.0.as_ptr())) };
+
+		anyhow!("TurboJPEG error: {}", message.to_string_lossy())
+	}
+
+	fn set(&self, param: raw::TJPARAM, value: c_int) -> Result<(), Error> {
+		if unsafe { raw::tj3Set(self.0.as_ptr(), c_int::try_from(param)?, value) } != 0 {
+			return Err(self.error());
+		}
+		Ok(())
+	}
+
+	fn get(&self, param: raw::TJPARAM) -> Result<c_int, Error> {
+		Ok(unsafe { raw::tj3Get(self.0.as_ptr(), c_int::try_from(param)?) })
+	}
+
+	fn read_header(&self, file: &[u8]) -> Result<DimensionsU32, Error> {
+		if unsafe {
+			raw::tj3DecompressHeader(self.0.as_ptr(), file.as_ptr(), file.len().try_into()?)
+		} != 0
+		{
+			return Err(self.error());
+		}
+
+		Ok(DimensionsU32::from((
+			u32::try_from(self.get(raw::TJPARAM_TJPARAM_JPEGWIDTH)?)?,
+			u32::try_from(self.get(raw::TJPARAM_TJPARAM_JPEGHEIGHT)?)?,
+		)))
+	}
+
+	fn set_scaling_factor(&self, factor: turbojpeg::ScalingFactor) -> Result<(), Error> {
+		let factor = raw::tjscalingfactor {
+			num: c_int::try_from(factor.num())?,
+			denom: c_int::try_from(factor.denom())?,
+		};
+
+		if unsafe { raw::tj3SetScalingFactor(self.0.as_ptr(), factor) } != 0 {
+			return Err(self.error());
+		}
+		Ok(())
+	}
+
+	/// Decode only some of the rows of the scaled image, the destination is
+	/// then only those rows
+	fn set_cropping_region(&self, top: usize, rows: usize) -> Result<(), Error> {
+		let region = raw::tjregion {
+			x: 0,
+			y: c_int::try_from(top)?,
+			w: 0,
+			h: c_int::try_from(rows)?,
+		};
+
+		if unsafe { raw::tj3SetCroppingRegion(self.0.as_ptr(), region) } != 0 {
+			return Err(self.error());
+		}
+		Ok(())
+	}
+
+	/// Returns the warning if the image was only partially decoded
+	fn decompress(
+		&self,
+		file: &[u8],
+		pixels: &mut [u8],
+		pitch: i32,
+		format: turbojpeg::PixelFormat,
+	) -> Result<Option<String>, Error> {
+		if unsafe {
+			raw::tj3Decompress8(
+				self.0.as_ptr(),
+				file.as_ptr(),
+				file.len().try_into()?,
+				pixels.as_mut_ptr(),
+				pitch,
+				format as c_int,
+			)
+		} == 0
+		{
+			return Ok(None);
+		}
+
+		let err = self.error();
+
+		if i64::from(unsafe { raw::tj3GetErrorCode(self.0.as_ptr()) })
+			== i64::from(raw::TJERR_TJERR_WARNING)
+		{
+			Ok(Some(err.to_string()))
+		} else {
+			Err(err)
+		}
+	}
+}
+
+impl Drop for Decompressor {
+	fn drop(&mut self) {
+		unsafe { raw::tj3Destroy(self.0.as_ptr()) };
+	}
+}
+
+/// Number of rows of the scaled image to decode at a time, or `None` if the
+/// image can only be decoded in one pass
+///
+/// Most images are decoded in one band. Progressive images are always decoded
+/// in one pass because the whole image would be decompressed again for every
+/// band, and lossless images can't be partially decoded.
+fn band_rows(
+	decompressor: &Decompressor,
+	dimensions: DimensionsU32,
+	factor: turbojpeg::ScalingFactor,
+) -> Result<Option<usize>, Error> {
+	if decompressor.get(raw::TJPARAM_TJPARAM_PROGRESSIVE)? != 0
+		|| decompressor.get(raw::TJPARAM_TJPARAM_LOSSLESS)? != 0
+	{
+		return Ok(None);
+	}
+
+	let width = u64::from(u32::from(dimensions.width));
+	let height = u32::from(dimensions.height);
+	let bands = usize::try_from((width * u64::from(height)).div_ceil(BAND_PIXELS))?.max(1);
+	let mcu_rows = factor.scale(MCU_HEIGHT).max(1);
+	let rows = factor.scale(usize::try_from(height)?).div_ceil(bands);
+
+	Ok(Some(rows.div_ceil(mcu_rows).max(1) * mcu_rows))
+}
+
+/// Rows from the top of the image that were decoded before the data ran out
+/// or became corrupt, in a band of `rows` starting at `top` that had a
+/// warning when it was decoded
+///
+/// Decoding stops after the last row of the cropping region without reading
+/// the rest of the file, so fewer rows of the band are decoded until there's
+/// no warning. Returns `None` if the warning isn't caused by the image data
+/// (it happens for the first rows of the image).
+fn decoded_rows(
+	decode: &mut impl FnMut(usize, usize) -> Result<Option<String>, Error>,
+	top: usize,
+	rows: usize,
+	mcu_rows: usize,
+	cancel: &Cancel,
+) -> Result<Option<usize>, Error> {
+	// Multiples of `mcu_rows` that decode without a warning, and with one
+	let mut good = 0;
+	let mut bad = rows.div_ceil(mcu_rows);
+
+	while bad - good > 1 {
+		let middle = good.midpoint(bad);
+
+		cancel.check()?;
+
+		if decode(top, (middle * mcu_rows).min(rows))?.is_none() {
+			good = middle;
+		} else {
+			bad = middle;
+		}
+	}
+
+	if top == 0 && good == 0 {
+		return Ok(None);
+	}
+
+	Ok(Some(top + (good * mcu_rows).min(rows)))
+}
+
+/// Smallest scaling factor that decodes the image to at least `minimum`
+fn scaling_factor(dimensions: DimensionsU32, minimum: DimensionsU32) -> turbojpeg::ScalingFactor {
+	static FACTORS: LazyLock<Vec<turbojpeg::ScalingFactor>> =
+		LazyLock::new(turbojpeg::Decompressor::supported_scaling_factors);
+
+	FACTORS
+		.iter()
+		.copied()
+		.filter(|factor| factor.num() <= factor.denom())
+		.filter(|&factor| scale(dimensions, factor).covers(minimum))
+		.min_by_key(|&factor| scale(dimensions, factor).width)
+		.unwrap_or(turbojpeg::ScalingFactor::ONE)
+}
+
+fn scale(dimensions: DimensionsU32, factor: turbojpeg::ScalingFactor) -> DimensionsU32 {
+	let scale_u32 = |value: u32| {
+		usize::try_from(value)
+			.ok()
+			.and_then(|value| u32::try_from(factor.scale(value)).ok())
+			.unwrap_or(value)
+	};
+
+	DimensionsU32::from((
+		scale_u32(dimensions.width.into()),
+		scale_u32(dimensions.height.into()),
+	))
 }
 
 #[derive(Debug, derive_more::Constructor)]
@@ -0,0 +1,190 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Decoders that can't output pixels in cairo's XRGB layout (native endian
+// 0x00RRGGBB) write them into the image data buffer in their own byte order
+// and the channels are then rearranged in place, or copied one row at a time
+// when the decoder has its own buffer.
+
+use crate::fiv::image::Pixel;
+use bytemuck::cast_slice_mut;
+
+/// Convert RGBA bytes (stored in the pixels) to XRGB in place
+pub fn rgba_in_place(pixels: &mut [Pixel]) {
+	#[cfg(target_arch = "x86_64")]
+	if is_x86_feature_detected!("ssse3") {
+		// SAFETY: The CPU supports SSSE3
+		unsafe { x86_64::rgba_in_place(pixels) };
+		return;
+	}
+
+	for pixel in pixels {
+		*pixel = u32::from_be(*pixel) >> 8;
+	}
+}
+
+/// Convert a row of RGBA bytes to XRGB
+pub fn rgba_row(src: &[u8], dst: &mut [Pixel]) {
+	let len = dst.len().min(src.len() / 4);
+	let dst = &mut dst[..len];
+
+	// The decoder's rows aren't necessarily aligned for `Pixel`
+	cast_slice_mut::<Pixel, u8>(dst).copy_from_slice(&src[..len * 4]);
+	rgba_in_place(dst);
+}
+
+/// Convert a row of 16-bit little endian RGB samples with `bits` of precision
+/// to XRGB
+pub fn hdr_rgb_row(src: &[u8], dst: &mut [Pixel], bits: u8) {
+	let shift = bits.saturating_sub(8);
+
+	for (samples, pixel) in src.chunks_exact(6).zip(dst.iter_mut()) {
+		let channel = |index: usize| {
+			u32::from(u16::from_le_bytes([samples[index], samples[index + 1]]) >> shift).min(0xFF)
+		};
+
+		*pixel = (channel(0) << 16) | (channel(2) << 8) | channel(4);
+	}
+}
+
+/// Expand 8-bit samples with `channels` of grey, grey and alpha, RGB or RGBA
+/// that have been read into the start of the pixels to XRGB in place
+pub fn expand_in_place(pixels: &mut [Pixel], channels: usize) {
+	if channels == 4 {
+		rgba_in_place(pixels);
+		return;
+	}
+
+	let bytes: &mut [u8] = cast_slice_mut(pixels);
+
+	// Every pixel is wider than its samples, so working backwards from the
+	// end never overwrites samples that haven't been converted yet
+	for index in (0..bytes.len() / 4).rev() {
+		let src = &bytes[index * channels..(index + 1) * channels];
+		let pixel = if channels < 3 {
+			(u32::from(src[0]) << 16) | (u32::from(src[0]) << 8) | u32::from(src[0])
+		} else {
+			(u32::from(src[0]) << 16) | (u32::from(src[1]) << 8) | u32::from(src[2])
+		};
+
+		bytes[index * 4..(index + 1) * 4].copy_from_slice(&pixel.to_ne_bytes());
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+	use super::Pixel;
+	use std::arch::x86_64::{
+		__m128i, _mm_loadu_si128, _mm_setr_epi8, _mm_shuffle_epi8, _mm_storeu_si128,
+	};
+
+	/// Move R, G and B from bytes 0, 1 and 2 to bytes 2, 1 and 0, and clear
+	/// byte 3 (a negative index clears the byte)
+	#[target_feature(enable = "ssse3")]
+	pub unsafe fn rgba_in_place(pixels: &mut [Pixel]) {
+		let mask = _mm_setr_epi8(2, 1, 0, -1, 6, 5, 4, -1, 10, 9, 8, -1, 14, 13, 12, -1);
+		let mut chunks = pixels.chunks_exact_mut(4);
+
+		for chunk in &mut chunks {
+			#[expect(clippy::cast_ptr_alignment, reason = "loads and stores are unaligned")]
+			let chunk = chunk.as_mut_ptr().cast::<__m128i>();
+
+			// SAFETY: The chunk is 16 bytes and the access is unaligned
+			unsafe { _mm_storeu_si128(chunk, _mm_shuffle_epi8(_mm_loadu_si128(chunk), mask)) };
+		}
+
+		for pixel in chunks.into_remainder() {
+			*pixel = u32::from_be(*pixel) >> 8;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RGBA: [u8; 20] = [
+		0x11, 0x22, 0x33, 0xFF, 0x44, 0x55, 0x66, 0x00, 0x77, 0x88, 0x99, 0x80, 0xAA, 0xBB, 0xCC,
+		0x01, 0xDD, 0xEE, 0xFF, 0x7F,
+	];
+	const XRGB: [Pixel; 5] = [
+		0x0011_2233,
+		0x0044_5566,
+		0x0077_8899,
+		0x00AA_BBCC,
+		0x00DD_EEFF,
+	];
+
+	#[test]
+	fn rgba() {
+		let mut pixels = [0; 5];
+
+		cast_slice_mut::<Pixel, u8>(&mut pixels).copy_from_slice(&RGBA);
+		rgba_in_place(&mut pixels);
+		assert_eq!(pixels, XRGB);
+
+		// Unaligned rows
+		let mut src = vec![0; RGBA.len() + 1];
+
+		src[1..].copy_from_slice(&RGBA);
+		rgba_row(&src[1..], &mut pixels);
+		assert_eq!(pixels, XRGB);
+	}
+
+	#[test]
+	fn expand() {
+		let rgb: Vec<u8> = RGBA
+			.chunks_exact(4)
+			.flat_map(|pixel| pixel[..3].to_vec())
+			.collect();
+		let mut pixels = [0; 5];
+
+		cast_slice_mut::<Pixel, u8>(&mut pixels)[..rgb.len()].copy_from_slice(&rgb);
+		expand_in_place(&mut pixels, 3);
+		assert_eq!(pixels, XRGB);
+
+		cast_slice_mut::<Pixel, u8>(&mut pixels)[..10]
+			.copy_from_slice(&[0x12, 0xFF, 0x34, 0x00, 0x56, 0x80, 0x78, 0x01, 0x9A, 0x7F]);
+		expand_in_place(&mut pixels, 2);
+		assert_eq!(
+			pixels,
+			[
+				0x0012_1212,
+				0x0034_3434,
+				0x0056_5656,
+				0x0078_7878,
+				0x009A_9A9A
+			]
+		);
+	}
+
+	#[test]
+	fn hdr_rgb() {
+		let mut pixels = [0; 2];
+
+		hdr_rgb_row(
+			&[
+				0xFF, 0x03, 0x00, 0x02, 0x04, 0x00, // 10-bit
+				0x00, 0x00, 0xFF, 0x01, 0x00, 0x04, // Out of range
+			],
+			&mut pixels,
+			10,
+		);
+		assert_eq!(pixels, [0x00FF_8001, 0x0000_7FFF]);
+	}
+}
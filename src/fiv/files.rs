@@ -16,21 +16,47 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+use super::codecs::Cancel;
+use super::numeric::DimensionsU32;
+use super::session::{Session, SessionFile, SessionView, ZoomMode};
+use super::trash::{self, Trashed};
+use super::util::memory;
 use super::{CommandLineArgs, CommandLineFilenames, Image, Mark, Orientation, Rotate, Waitable};
+use anyhow::Error;
 use async_notify::Notify;
 use gtk::glib::clone::Downgrade;
-use itertools::interleave;
+use gtk::{cairo, glib};
 use log::{debug, error, trace};
-use pariter::IteratorExt;
 use std::cmp::min;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::iter;
-use std::path::PathBuf;
-use std::sync::atomic::{self, AtomicBool};
-use std::sync::{Arc, Condvar, Mutex};
-use std::time::Instant;
+use std::num::NonZero;
+use std::path::{self, Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 
+/// Preload more images in the direction of travel, by this ratio
+const TRAVEL_RATIO: usize = 3;
+
+/// Navigation older than this doesn't count towards the direction of travel
+const TRAVEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Navigation faster than this is from a key being held down
+const HELD_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Interval between checks for memory pressure
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Minimum increase in the preload memory limits for each check without
+/// memory pressure (they double otherwise)
+const PRESSURE_RECOVERY: u64 = 64 * 1024 * 1024;
+
+/// Time to wait after navigation stops before prioritising the current image
+/// when a key was held down
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
 #[derive(Debug)]
 pub struct Files {
 	args: CommandLineArgs,
@@ -38,6 +64,8 @@ pub struct Files {
 	state: Mutex<State>,
 	notify: Notify,
 	seq_pool: ThreadPool,
+	session_file: Option<SessionFile>,
+	session_saving: AtomicBool,
 
 	/// `start()` has finished or loaded at least one image
 	start_ready: Waitable<bool>,
@@ -59,6 +87,14 @@ pub struct Current {
 	pub position: usize,
 	pub total: usize,
 	pub mark: Option<bool>,
+	pub filter: Filter,
+
+	/// Images to display, with the current image in the active pane
+	pub panes: Vec<Option<Arc<Image>>>,
+	pub active_pane: usize,
+
+	/// Position in the unfiltered list of images
+	index: usize,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -67,6 +103,86 @@ pub enum Navigate {
 	Previous,
 	Next,
 	Last,
+	Backward(usize),
+	Forward(usize),
+	Random,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Direction {
+	Backward,
+	Forward,
+}
+
+/// Recent navigation, to find the direction and speed of travel
+#[derive(Debug, Default)]
+struct Travel {
+	recent: VecDeque<(Instant, Direction)>,
+}
+
+/// Files that haven't been opened yet, by their position in the list of
+/// filenames, while the rest of the list is still being made
+#[derive(Debug, Default)]
+struct Probes {
+	pending: BTreeMap<usize, PathBuf>,
+
+	/// Number of filenames that have been listed
+	listed: usize,
+
+	/// All of the filenames have been listed
+	finished: bool,
+}
+
+/// The current image stays on the image to start on until that file has been
+/// opened or skipped, regardless of the order that files are opened in
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Start {
+	/// Looking for the current image from the previous session in the list
+	/// of filenames
+	Listing,
+
+	/// Waiting for the file at this position in the list of filenames
+	Waiting(usize),
+
+	Started,
+}
+
+/// How images should be preloaded based on recent navigation
+#[derive(Debug, Default, Copy, Clone)]
+struct Heading {
+	direction: Option<Direction>,
+
+	/// Number of images that are likely to be passed before navigation stops
+	passing: usize,
+}
+
+/// An operation that has been performed on an image that can be undone
+#[derive(Debug, Clone)]
+struct Operation {
+	image: Arc<Image>,
+	change: Change,
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+	Mark { from: bool, to: bool },
+	Orientation(Orientation),
+	Trash { trashed: Trashed, position: usize },
+	Restore { trashed: Trashed, position: usize },
+}
+
+#[derive(Debug, Default)]
+struct History {
+	undo: Vec<Operation>,
+	redo: Vec<Operation>,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Filter {
+	#[default]
+	All,
+	Marked,
+	Unmarked,
 }
 
 impl Startup {
@@ -79,17 +195,221 @@ impl Startup {
 	}
 }
 
+impl Filter {
+	fn matches(self, image: &Image) -> bool {
+		match self {
+			Filter::All => true,
+			Filter::Marked => image.marked() == Some(true),
+			Filter::Unmarked => image.marked() == Some(false),
+		}
+	}
+}
+
+impl Operation {
+	fn inverse(&self) -> Self {
+		Self {
+			image: self.image.clone(),
+			change: match &self.change {
+				Change::Mark { from, to } => Change::Mark {
+					from: *to,
+					to: *from,
+				},
+				Change::Orientation(add) => Change::Orientation(-*add),
+				Change::Trash { trashed, position } => Change::Restore {
+					trashed: trashed.clone(),
+					position: *position,
+				},
+				Change::Restore { trashed, position } => Change::Trash {
+					trashed: trashed.clone(),
+					position: *position,
+				},
+			},
+		}
+	}
+
+	/// Blocking on I/O
+	fn apply(&self, files: &Files) {
+		match &self.change {
+			Change::Mark { to, .. } => self.image.mark(if *to { Mark::Set } else { Mark::Unset }),
+			Change::Orientation(add) => {
+				self.image.add_orientation(*add);
+				files.state.lock().unwrap().changed_orientation(&self.image);
+			}
+			Change::Trash { trashed, .. } => match trashed.trash() {
+				Ok(()) => {
+					self.image.trash_mark();
+					files.state.lock().unwrap().remove(&self.image);
+				}
+				Err(err) => error!("{}: {err}", self.image.filename.display()),
+			},
+			Change::Restore { trashed, position } => match trashed.restore() {
+				Ok(()) => {
+					self.image.restore_mark();
+					files
+						.state
+						.lock()
+						.unwrap()
+						.insert(*position, self.image.clone());
+				}
+				Err(err) => error!("{}: {err}", self.image.filename.display()),
+			},
+		}
+	}
+}
+
+impl Travel {
+	const RECENT: usize = 4;
+
+	pub fn push(&mut self, action: Navigate) {
+		let direction = match action {
+			Navigate::Previous | Navigate::Backward(_) => Direction::Backward,
+			Navigate::Next | Navigate::Forward(_) => Direction::Forward,
+			Navigate::First | Navigate::Last | Navigate::Random => {
+				self.recent.clear();
+				return;
+			}
+		};
+
+		if self.recent.len() == Self::RECENT {
+			self.recent.pop_front();
+		}
+		self.recent.push_back((Instant::now(), direction));
+	}
+
+	pub fn clear(&mut self) {
+		self.recent.clear();
+	}
+
+	/// Returns a direction if the recent navigation has all been in the same
+	/// direction, or a key is being held down
+	pub fn heading(&self) -> Heading {
+		let Some(&(last_time, last)) = self.recent.back() else {
+			return Heading::default();
+		};
+		let same: Vec<Instant> = self
+			.recent
+			.iter()
+			.rev()
+			.take_while(|(_, direction)| *direction == last)
+			.map(|(time, _)| *time)
+			.collect();
+		let now = Instant::now();
+
+		if now.duration_since(last_time) > TRAVEL_TIMEOUT {
+			return Heading::default();
+		}
+
+		// Key repeat is faster than anyone can press a key
+		let passing = if same.len() >= 2 && now.duration_since(last_time) < HELD_INTERVAL {
+			let elapsed = same[0].duration_since(same[same.len() - 1]);
+			let interval = elapsed / u32::try_from(same.len() - 1).unwrap();
+
+			if interval < HELD_INTERVAL && !interval.is_zero() {
+				usize::try_from(SETTLE_TIME.as_nanos() / interval.as_nanos()).unwrap_or(usize::MAX)
+			} else {
+				0
+			}
+		} else {
+			0
+		};
+
+		Heading {
+			direction: (same.len() >= 3 || passing > 0).then_some(last),
+			passing,
+		}
+	}
+}
+
+impl Probes {
+	pub fn push(&mut self, order: usize, filename: PathBuf) {
+		self.pending.insert(order, filename);
+		self.listed = order + 1;
+	}
+
+	/// Returns the file closest to the centre, preferring files after it
+	pub fn next(&mut self, centre: usize) -> Option<(usize, PathBuf)> {
+		let after = self.pending.range(centre..).next().map(|(&order, _)| order);
+		let before = self
+			.pending
+			.range(..centre)
+			.next_back()
+			.map(|(&order, _)| order);
+
+		let order = match (before, after) {
+			(Some(before), Some(after)) => {
+				if centre - before < after - centre {
+					before
+				} else {
+					after
+				}
+			}
+			(before, after) => after.or(before)?,
+		};
+
+		self.pending.remove_entry(&order)
+	}
+}
+
+impl History {
+	pub fn push(&mut self, operation: Operation) {
+		self.undo.push(operation);
+		self.redo.clear();
+	}
+
+	/// Returns the operation to apply to undo the last operation
+	pub fn undo(&mut self) -> Option<Operation> {
+		let operation = self.undo.pop()?;
+		let inverse = operation.inverse();
+
+		self.redo.push(operation);
+		Some(inverse)
+	}
+
+	/// Update operations to refer to a new image that replaces an old image
+	pub fn replace(&mut self, old: &Image, new: &Arc<Image>) {
+		for operation in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+			if *operation.image == *old {
+				operation.image = new.clone();
+			}
+		}
+	}
+
+	/// Returns the operation to apply to redo the last undone operation
+	pub fn redo(&mut self) -> Option<Operation> {
+		let operation = self.redo.pop()?;
+
+		self.undo.push(operation.clone());
+		Some(operation)
+	}
+}
+
 impl Files {
 	pub fn new(args: CommandLineArgs, startup: Instant) -> Arc<Files> {
 		let preload_count = usize::try_from(args.preload_count).unwrap_or(usize::MAX);
-		let preload_memory = args.preload_memory;
+		let preload_memory = args.preload_memory.unwrap_or_else(automatic_preload_memory);
+		let full_memory = args.full_memory.unwrap_or_else(automatic_full_memory);
 		let shutdown = Arc::new(AtomicBool::new(false));
+		let session_file = SessionFile::new(&args.filenames)
+			.map_err(|err| error!("Session: {err}"))
+			.ok();
+		let session = session_file
+			.as_ref()
+			.map(SessionFile::load)
+			.unwrap_or_default();
 		let files = Arc::new(Files {
 			args,
 			startup: Mutex::new(Startup::new(startup)),
-			state: Mutex::new(State::new(preload_count, preload_memory, shutdown.clone())),
+			state: Mutex::new(State::new(
+				preload_count,
+				preload_memory,
+				full_memory,
+				shutdown.clone(),
+				session,
+			)),
 			notify: Notify::new(),
 			seq_pool: ThreadPool::new(1),
+			session_file,
+			session_saving: AtomicBool::new(false),
 			start_ready: Waitable::new(false),
 			start_finished: Waitable::new(false),
 			shutdown,
@@ -103,6 +423,40 @@ impl Files {
 		self.args.mark_directory.is_some()
 	}
 
+	pub fn confirm_trash(&self) -> bool {
+		self.args.confirm_trash
+	}
+
+	pub fn enlarge_filter(&self) -> cairo::Filter {
+		self.args.enlarge_filter.into()
+	}
+
+	pub fn reduce_filter(&self) -> cairo::Filter {
+		self.args.reduce_filter.into()
+	}
+
+	/// Preload images at a size that fits the display
+	pub fn set_display_size(&self, display: DimensionsU32) {
+		let state = self.state.lock().unwrap();
+
+		if state.preload.set_display(display) {
+			state.preload(false);
+		}
+	}
+
+	/// Scale that the visible images are being drawn at, in device pixels
+	pub fn zoom(&self, scale: Option<f64>) {
+		self.state.lock().unwrap().preload.zoom(scale);
+	}
+
+	pub fn pan_step(&self) -> f64 {
+		self.args.pan_step.into()
+	}
+
+	pub fn slideshow_interval(&self) -> Duration {
+		self.args.slideshow_interval
+	}
+
 	pub fn begin(&self) -> Instant {
 		self.startup.lock().unwrap().begin
 	}
@@ -112,50 +466,176 @@ impl Files {
 		let shutdown_copy = self.shutdown.clone();
 
 		std::thread::spawn(move || {
-			pariter::scope(|scope| {
-				let canonical_mark_directory =
-					self_copy
-						.args
-						.mark_directory
-						.as_ref()
-						.and_then(|directory| {
-							directory
-								.canonicalize()
-								.map_err(|err| error!("{}: {err}", directory.display()))
-								.ok()
-						});
+			let canonical_mark_directory =
+				self_copy
+					.args
+					.mark_directory
+					.as_ref()
+					.and_then(|directory| {
+						directory
+							.canonicalize()
+							.map_err(|err| error!("{}: {err}", directory.display()))
+							.ok()
+					});
+			let probes = (Mutex::new(Probes::default()), Condvar::new());
+			let threads = std::thread::available_parallelism().map_or(1, NonZero::get);
+
+			// Files are opened while the rest of the filenames are still being
+			// listed, closest to the current image first so that it doesn't
+			// take as long to be able to navigate around it
+			std::thread::scope(|scope| {
+				for _ in 0..threads {
+					scope.spawn(|| self_copy.probe(&probes, canonical_mark_directory.as_ref()));
+				}
 
-				CommandLineFilenames::new(&self_copy.args, shutdown_copy.clone())
-					.parallel_map_scoped(scope, move |filename| {
-						if shutdown_copy.load(atomic::Ordering::Acquire) {
-							None
-						} else {
-							Image::new(canonical_mark_directory.as_ref(), &filename)
-								.map_err(|err| error!("{}: {err}", filename.display()))
-								.ok()
-						}
-					})
-					.flatten()
-					.for_each(|image| self_copy.add(image));
+				let filenames = CommandLineFilenames::new(&self_copy.args, shutdown_copy.clone());
 
-				debug!(
-					"Files added from command line in {:?}",
-					self_copy.startup.lock().unwrap().begin.elapsed()
-				);
+				for (order, filename) in filenames.enumerate() {
+					self_copy.listed(order, &filename);
+					probes.0.lock().unwrap().push(order, filename);
+					probes.1.notify_one();
+				}
 
-				self_copy.start_ready.set(true);
-				self_copy.start_finished.set(true);
-				self_copy.update_ui();
-			})
-			.unwrap();
+				self_copy.listed_all();
+				probes.0.lock().unwrap().finished = true;
+				probes.1.notify_all();
+			});
+
+			debug!(
+				"Files added from command line in {:?}",
+				self_copy.startup.lock().unwrap().begin.elapsed()
+			);
+
+			// Stop waiting for the last image if it's no longer there
+			let mut state = self_copy.state.lock().unwrap();
+
+			state.restore = Session::default();
+			state.start = Start::Started;
+			drop(state);
+
+			self_copy.start_ready.set(true);
+			self_copy.start_finished.set(true);
+			self_copy.save_session();
+			self_copy.update_ui();
 		});
 
 		self.start_ready.wait(&true);
+		self.monitor_memory();
 
 		let state = self.state.lock().unwrap();
 		!state.images.is_empty()
 	}
 
+	/// Open files until there are none left to open
+	///
+	/// Blocking on I/O
+	fn probe(&self, probes: &(Mutex<Probes>, Condvar), mark_directory: Option<&PathBuf>) {
+		while !self.shutdown.load(atomic::Ordering::Acquire) {
+			let centre = self.probe_centre();
+			let mut probes_mg = probes.0.lock().unwrap();
+			let (order, filename) = loop {
+				let listed = probes_mg.listed;
+
+				if let Some(next) = probes_mg.next(centre.unwrap_or(listed)) {
+					break next;
+				}
+
+				if probes_mg.finished {
+					return;
+				}
+
+				probes_mg = probes.1.wait(probes_mg).unwrap();
+			};
+			drop(probes_mg);
+
+			match Image::new(mark_directory, order, &filename) {
+				Ok(image) => self.add(image),
+				Err(err) => self.skip(order, &filename, &err),
+			}
+		}
+	}
+
+	/// Position in the list of filenames that files should be opened around,
+	/// or `None` to open the files that were listed most recently first
+	/// while looking for the current image from the previous session
+	fn probe_centre(&self) -> Option<usize> {
+		let state = self.state.lock().unwrap();
+
+		match state.start {
+			Start::Listing => None,
+			Start::Waiting(order) => Some(order),
+			Start::Started => Some(
+				state
+					.images
+					.get(state.position)
+					.map_or(0, |image| image.order),
+			),
+		}
+	}
+
+	/// Look for the current image from the previous session as filenames are
+	/// listed
+	fn listed(&self, order: usize, filename: &Path) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.start == Start::Listing
+			&& let Some(restore) = state.restore.filename.as_ref()
+			&& path::absolute(filename).is_ok_and(|filename| filename == *restore)
+		{
+			state.start = Start::Waiting(order);
+		}
+	}
+
+	/// Start on the first image if the current image from the previous session
+	/// wasn't found
+	fn listed_all(&self) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.start == Start::Listing {
+			state.start = Start::Waiting(0);
+			self.check_start(&mut state);
+		}
+	}
+
+	/// Move to the image to start on once it has been opened
+	fn check_start(&self, state: &mut State) {
+		if state.advance_start() {
+			debug!(
+				"Starting image added after {:?}",
+				self.startup.lock().unwrap().begin.elapsed()
+			);
+
+			state.preload(false);
+			self.start_ready.set(true);
+		}
+	}
+
+	/// Preload fewer images while the system is under memory pressure
+	fn monitor_memory(self: &Arc<Self>) {
+		let self_ref = self.downgrade();
+
+		std::thread::spawn(move || {
+			loop {
+				std::thread::sleep(MEMORY_CHECK_INTERVAL);
+
+				let Some(self_copy) = self_ref.upgrade() else {
+					return;
+				};
+
+				if self_copy.shutdown.load(atomic::Ordering::Acquire) {
+					return;
+				}
+
+				let pressure = memory::under_pressure();
+				let state = self_copy.state.lock().unwrap();
+
+				if state.preload.memory_pressure(pressure) {
+					state.preload(false);
+				}
+			}
+		});
+	}
+
 	pub fn shutdown(&self) {
 		if self
 			.shutdown
@@ -179,6 +659,8 @@ impl Files {
 		debug!("Waiting for background tasks to finish...");
 		self.seq_pool.join();
 		debug!("Background tasks complete in {:?}", begin.elapsed());
+
+		self.write_session();
 	}
 
 	fn add(&self, image: Arc<Image>) {
@@ -187,17 +669,168 @@ impl Files {
 		}
 
 		let mut state = self.state.lock().unwrap();
-		if state.add(image) {
+		let restore = state.restore(&image);
+
+		state.add(image);
+
+		if state.images.len() == 1 {
 			debug!(
 				"First image added after {:?}",
 				self.startup.lock().unwrap().begin.elapsed()
 			);
+		}
 
-			self.start_ready.set(true);
+		// Move to the last image from the previous session as soon as it's
+		// found, without waiting for all the other files
+		if restore {
+			debug!(
+				"Last image restored after {:?}",
+				self.startup.lock().unwrap().begin.elapsed()
+			);
+
+			state.restored_zoom = Some(state.restored_view.zoom);
 		}
+
+		self.check_start(&mut state);
 		self.update_ui();
 	}
 
+	/// Files that aren't images aren't added or reported as failures (e.g.
+	/// sidecar files), but they're logged
+	fn skip(&self, order: usize, filename: &Path, err: &Error) {
+		error!("{}: {err}", filename.display());
+
+		let mut state = self.state.lock().unwrap();
+
+		state.skipped.push(order);
+		self.check_start(&mut state);
+	}
+
+	/// Every image that couldn't be opened or decoded and the reason, in the
+	/// order of the list of filenames
+	///
+	/// Files are only images if they have an image extension or their content
+	/// is an image type, other files aren't included. Decoding errors are only
+	/// known for images that have been decoded, use `check_failures()` to
+	/// decode the rest of them.
+	pub fn failures(&self) -> Vec<(PathBuf, String)> {
+		self.state
+			.lock()
+			.unwrap()
+			.images
+			.iter()
+			.filter_map(|image| {
+				image
+					.failure()
+					.map(|failure| (image.filename.clone(), failure.to_string()))
+			})
+			.collect()
+	}
+
+	/// Decode every image that hasn't been decoded yet in the background,
+	/// until `cancel` is set, so that `failures()` includes all of them
+	///
+	/// Returns the number of images that are still to be checked
+	pub fn check_failures(&self, cancel: &Cancel) -> Arc<AtomicUsize> {
+		let images: VecDeque<Arc<Image>> = self
+			.state
+			.lock()
+			.unwrap()
+			.images
+			.iter()
+			.filter(|image| !image.decoded())
+			.cloned()
+			.collect();
+		let remaining = Arc::new(AtomicUsize::new(images.len()));
+		let threads = std::thread::available_parallelism()
+			.map_or(1, NonZero::get)
+			.min(images.len());
+		let images = Arc::new(Mutex::new(images));
+
+		for _ in 0..threads {
+			let images = images.clone();
+			let remaining = remaining.clone();
+			let cancel = cancel.clone();
+			let shutdown = self.shutdown.clone();
+
+			std::thread::spawn(move || {
+				while !cancel.cancelled() && !shutdown.load(atomic::Ordering::Acquire) {
+					let Some(image) = images.lock().unwrap().pop_front() else {
+						break;
+					};
+
+					image.check(&cancel);
+					remaining.fetch_sub(1, atomic::Ordering::AcqRel);
+				}
+			});
+		}
+
+		remaining
+	}
+
+	/// The view settings from the previous session
+	pub fn restored_view(&self) -> SessionView {
+		self.state.lock().unwrap().restored_view
+	}
+
+	/// Returns the zoom mode from the previous session once, after moving to
+	/// the last image from that session
+	pub fn take_restored_zoom(&self) -> Option<ZoomMode> {
+		self.state.lock().unwrap().restored_zoom.take()
+	}
+
+	pub fn session_view(self: &Arc<Self>, view: SessionView) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.view != view {
+			state.view = view;
+			drop(state);
+
+			self.save_session();
+		}
+	}
+
+	/// Save the session in the background, unless a save is already pending
+	fn save_session(self: &Arc<Self>) {
+		if self.session_file.is_none() || self.session_saving.swap(true, atomic::Ordering::AcqRel) {
+			return;
+		}
+
+		let self_copy = self.clone();
+
+		self.seq_pool.execute(move || {
+			self_copy
+				.session_saving
+				.store(false, atomic::Ordering::Release);
+			self_copy.write_session();
+		});
+	}
+
+	/// Blocking on I/O
+	fn write_session(&self) {
+		let Some(session_file) = &self.session_file else {
+			return;
+		};
+		let mut state = self.state.lock().unwrap();
+
+		// Don't overwrite the previous session until it has been restored
+		let Some(session) = state.session() else {
+			return;
+		};
+		let text = session_file.serialise(&session);
+
+		if state.saved_session.as_ref() == Some(&text) {
+			return;
+		}
+
+		state.saved_session = Some(text.clone());
+		drop(state);
+
+		if let Err(err) = session_file.save(&text) {
+			error!("{}: {err}", session_file.filename().display());
+		}
+	}
+
 	pub fn starting(&self) -> bool {
 		!self.start_finished.get()
 	}
@@ -221,7 +854,13 @@ impl Files {
 		self.state.lock().unwrap().position()
 	}
 
+	/// Blocking on CPU, I/O if the file has changed
 	pub fn loaded(&self, image: &Image) {
+		if image.changed() && !self.shutdown.load(atomic::Ordering::Acquire) {
+			self.reopen(image);
+			return;
+		}
+
 		let state = self.state.lock().unwrap();
 		let current = state.current();
 
@@ -257,6 +896,22 @@ impl Files {
 		}
 	}
 
+	/// Replace an image with a new one after its file was changed while it
+	/// was open, so that it's loaded again from the new file
+	///
+	/// Blocking on CPU, I/O
+	fn reopen(&self, image: &Image) {
+		match image.reload() {
+			Ok(new_image) => {
+				debug!("{}: Reopened after it changed", image.filename.display());
+
+				self.state.lock().unwrap().replace(image, new_image);
+				self.update_ui();
+			}
+			Err(err) => error!("{}: {err}", image.filename.display()),
+		}
+	}
+
 	/// Run a long task sequentially in the background (for file I/O)
 	fn seq_execute<F: FnOnce(&Image) + Send + 'static>(
 		self: &Arc<Self>,
@@ -272,14 +927,14 @@ impl Files {
 			// To avoid wasting resources doing something that is no longer
 			// needed, check that we're still on the same image, unless this
 			// task must always be run
-			if (always || (!shutdown && current.position == self_copy.position()))
+			if (always || (!shutdown && current.index == self_copy.position()))
 				&& let Some(image) = current.image
 			{
 				func(&image);
 
 				// After running the task, if we're still on the same image
 				// then the UI for it needs to be updated
-				if current.position == self_copy.position() {
+				if current.index == self_copy.position() {
 					self_copy.update_ui();
 				}
 			}
@@ -287,49 +942,289 @@ impl Files {
 	}
 
 	pub fn navigate(self: &Arc<Self>, action: Navigate) {
+		self.move_with(|state| {
+			state.travel.push(action);
+			state.navigate(action);
+		});
+	}
+
+	/// Move to an image by its number (in the filtered list of images) or
+	/// the first image with a filename that matches the text
+	pub fn go_to(self: &Arc<Self>, text: &str) {
+		self.move_with(|state| {
+			state.travel.clear();
+
+			match text.parse::<usize>() {
+				Ok(number) => state.go_to(number),
+				Err(_) => state.find(text),
+			}
+		});
+	}
+
+	pub fn select(self: &Arc<Self>, image: &Arc<Image>) {
+		self.move_with(|state| {
+			state.travel.clear();
+			state.select(image);
+		});
+	}
+
+	/// Change the current image, using a function that returns a value to
+	/// pass back to the caller
+	fn move_with<F: FnOnce(&mut State) -> R, R>(self: &Arc<Self>, func: F) -> R {
 		let mut state = self.state.lock().unwrap();
 
-		state.navigate(action);
+		// Stop waiting for the last image from the previous session
+		state.restore.filename = None;
+		let previous = state.images.get(state.position).cloned();
+		let result = func(&mut state);
+		let moved = state.images.get(state.position) != previous.as_ref();
 
 		if self.args.mark_directory.is_some() {
 			self.seq_execute(state.current(), false, Image::refresh_mark);
 		}
+		drop(state);
+
+		if moved {
+			self.save_session();
+		}
 		self.update_ui();
+		result
 	}
 
-	pub fn orientation(self: &Arc<Self>, rotate: Rotate, horizontal_flip: bool) {
-		let mut state = self.state.lock().unwrap();
+	/// Use a random order of images for the slideshow, starting from the
+	/// current image
+	pub fn shuffle(&self, enable: bool) {
+		self.state.lock().unwrap().shuffle(enable);
+	}
+
+	/// Move to the next image in the slideshow, returning false if there are
+	/// no more images
+	pub fn slideshow_next(self: &Arc<Self>, repeat: bool) -> bool {
+		self.move_with(|state| {
+			state.travel.push(Navigate::Next);
+			state.slideshow_next(repeat)
+		})
+	}
+
+	pub fn orientation(self: &Arc<Self>, rotate: Rotate, horizontal_flip: bool) {
+		let mut state = self.state.lock().unwrap();
+		let add = Orientation::new(rotate, horizontal_flip);
 
-		state.orientation(Orientation::new(rotate, horizontal_flip));
+		if let Some(image) = state.orientation(add) {
+			state.history.push(Operation {
+				image,
+				change: Change::Orientation(add),
+			});
+		}
+		drop(state);
+
+		self.save_session();
 		self.update_ui();
 	}
 
 	pub fn mark(self: &Arc<Self>, mark: Mark) {
 		if self.args.mark_directory.is_some() {
-			self.seq_execute(self.state.lock().unwrap().current(), true, move |image| {
+			let self_copy = self.clone();
+			let current = self.state.lock().unwrap().current();
+			let image = current.image.clone();
+
+			self.seq_execute(current, true, move |_| {
+				let Some(image) = image else {
+					return;
+				};
+				let from = image.marked();
+
 				image.mark(mark);
+
+				let mut state = self_copy.state.lock().unwrap();
+				if let (Some(from), Some(to)) = (from, image.marked())
+					&& from != to
+				{
+					state.history.push(Operation {
+						image,
+						change: Change::Mark { from, to },
+					});
+				}
+
+				// The set of images to preload depends on the marks
+				if state.filter != Filter::All {
+					state.preload(false);
+				}
 			});
 		}
 	}
+
+	pub fn save_orientation(self: &Arc<Self>) {
+		let self_copy = self.clone();
+
+		self.seq_execute(
+			self.state.lock().unwrap().current(),
+			true,
+			move |image| match image.save_orientation() {
+				Ok(false) => (),
+				Ok(true) => match image.reload() {
+					Ok(new_image) => {
+						self_copy.state.lock().unwrap().replace(image, new_image);
+						self_copy.save_session();
+					}
+					Err(err) => error!("{}: {err}", image.filename.display()),
+				},
+				Err(err) => error!("{}: {err}", image.filename.display()),
+			},
+		);
+	}
+
+	pub fn trash(self: &Arc<Self>) {
+		let self_copy = self.clone();
+		let current = self.state.lock().unwrap().current();
+		let image = current.image.clone();
+
+		self.seq_execute(current, true, move |_| {
+			let Some(image) = image else {
+				return;
+			};
+
+			match trash::trash(&image.filename) {
+				Ok(trashed) => {
+					image.trash_mark();
+
+					let mut state = self_copy.state.lock().unwrap();
+
+					if let Some(position) = state.remove(&image) {
+						state.history.push(Operation {
+							image,
+							change: Change::Trash { trashed, position },
+						});
+					}
+					drop(state);
+
+					self_copy.save_session();
+					self_copy.update_ui();
+				}
+				Err(err) => error!("{}: {err}", image.filename.display()),
+			}
+		});
+	}
+
+	pub fn undo(self: &Arc<Self>) {
+		self.replay(History::undo);
+	}
+
+	pub fn redo(self: &Arc<Self>) {
+		self.replay(History::redo);
+	}
+
+	/// Operations are replayed in the background so that they're in sequence
+	/// with other operations that are still pending
+	fn replay(self: &Arc<Self>, func: fn(&mut History) -> Option<Operation>) {
+		let self_copy = self.clone();
+
+		self.seq_execute(self.state.lock().unwrap().current(), true, move |_| {
+			let Some(operation) = func(&mut self_copy.state.lock().unwrap().history) else {
+				return;
+			};
+
+			operation.apply(&self_copy);
+
+			// Move to the image so that the change is visible
+			self_copy.state.lock().unwrap().select(&operation.image);
+			self_copy.save_session();
+			self_copy.update_ui();
+		});
+	}
+
+	pub fn compare(self: &Arc<Self>, count: usize) {
+		self.state.lock().unwrap().compare(count);
+		self.update_ui();
+	}
+
+	pub fn navigate_pane(self: &Arc<Self>, action: Navigate) {
+		self.move_with(|state| state.navigate_pane(action));
+	}
+
+	pub fn filter(self: &Arc<Self>, filter: Filter) {
+		let mut state = self.state.lock().unwrap();
+
+		state.filter(filter);
+
+		if self.args.mark_directory.is_some() {
+			self.seq_execute(state.current(), false, Image::refresh_mark);
+		}
+		self.update_ui();
+	}
 }
 
 #[derive(Debug)]
 struct State {
 	images: Vec<Arc<Image>>,
 	position: usize,
+	filter: Filter,
+	history: History,
+	travel: Travel,
 	preload: Arc<Preload>,
+
+	/// Images shown side by side when comparing, the image in the active
+	/// pane is only updated when it stops being the active pane
+	panes: Vec<Option<Arc<Image>>>,
+	active_pane: usize,
+
+	/// Random order of images for the slideshow, or empty if it's in the
+	/// normal order
+	shuffled: Vec<Arc<Image>>,
+
+	/// Positions in the list of filenames of files that were skipped because
+	/// they aren't images
+	skipped: Vec<usize>,
+
+	start: Start,
+
+	/// Previous session that is being restored
+	restore: Session,
+	restored_view: SessionView,
+	restored_zoom: Option<ZoomMode>,
+	view: SessionView,
+
+	/// Orientations that have been changed but not saved to the file, by
+	/// absolute path
+	orientations: BTreeMap<PathBuf, Orientation>,
+	saved_session: Option<String>,
 }
 
 impl State {
-	fn new(preload_count: usize, preload_memory: u64, shutdown: Arc<AtomicBool>) -> Self {
+	fn new(
+		preload_count: usize,
+		preload_memory: u64,
+		full_memory: u64,
+		shutdown: Arc<AtomicBool>,
+		restore: Session,
+	) -> Self {
 		Self {
 			images: Vec::new(),
 			position: 0,
+			filter: Filter::default(),
+			history: History::default(),
+			travel: Travel::default(),
+			panes: Vec::new(),
+			active_pane: 0,
+			shuffled: Vec::new(),
+			skipped: Vec::new(),
+			start: if restore.filename.is_some() {
+				Start::Listing
+			} else {
+				Start::Waiting(0)
+			},
 			preload: Arc::new(Preload::new(
 				preload_count.saturating_add(1),
 				preload_memory,
+				full_memory,
 				shutdown,
 			)),
+			view: restore.view,
+			restored_view: restore.view,
+			restore,
+			restored_zoom: None,
+			orientations: BTreeMap::new(),
+			saved_session: None,
 		}
 	}
 
@@ -337,32 +1232,249 @@ impl State {
 		self.preload.start(files);
 	}
 
-	/// Returns true if this is the first image
-	pub fn add(&mut self, image: Arc<Image>) -> bool {
-		self.images.push(image);
+	/// Images are kept in the order of the list of filenames, regardless of
+	/// the order that they're opened in
+	pub fn add(&mut self, image: Arc<Image>) {
+		let index = self
+			.images
+			.partition_point(|other| other.order < image.order);
+
+		self.images.insert(index, image);
+
+		// Stay on the same image, the image to start on is found later
+		if self.start == Start::Started && self.images.len() > 1 && index <= self.position {
+			self.position += 1;
+		}
 
 		let first = self.images.len() == 1;
 		self.preload(!first);
-		first
+	}
+
+	/// Returns true if the image to start on has just been opened, skipping
+	/// past files that weren't opened because they aren't images
+	fn advance_start(&mut self) -> bool {
+		while let Start::Waiting(order) = self.start {
+			let index = self.images.partition_point(|image| image.order < order);
+
+			if self
+				.images
+				.get(index)
+				.is_some_and(|image| image.order == order)
+			{
+				self.position = index;
+				self.start = Start::Started;
+				return true;
+			}
+
+			if !self.skipped.contains(&order) {
+				return false;
+			}
+
+			self.start = Start::Waiting(order + 1);
+		}
+
+		false
+	}
+
+	/// Apply the previous session to an image as it's added
+	///
+	/// Returns true if this was the current image in the previous session
+	fn restore(&mut self, image: &Image) -> bool {
+		if self.restore.filename.is_none() && self.restore.orientations.is_empty() {
+			return false;
+		}
+
+		let Ok(filename) = path::absolute(&image.filename) else {
+			return false;
+		};
+
+		if let Some(orientation) = self.restore.orientations.remove(&filename) {
+			image.set_orientation(orientation);
+			self.changed_orientation(image);
+		}
+
+		self.restore
+			.filename
+			.take_if(|other| *other == filename)
+			.is_some()
+	}
+
+	/// Returns `None` if the previous session is still being restored
+	fn session(&self) -> Option<Session> {
+		if self.restore.filename.is_some() {
+			return None;
+		}
+
+		Some(Session {
+			filename: self
+				.images
+				.get(self.position)
+				.and_then(|image| path::absolute(&image.filename).ok()),
+			view: self.view,
+			orientations: self.orientations.clone(),
+		})
+	}
+
+	/// Record the orientation of an image for the session if it's different
+	/// to the orientation in the file
+	fn changed_orientation(&mut self, image: &Image) {
+		let Ok(filename) = path::absolute(&image.filename) else {
+			return;
+		};
+
+		if image.orientation() == image.metadata.orientation {
+			self.orientations.remove(&filename);
+		} else {
+			self.orientations.insert(filename, image.orientation());
+		}
 	}
 
 	fn preload(&self, only_if_starved: bool) {
-		self.preload
-			.update(&self.images, self.position, only_if_starved);
+		// Images in other panes are always loaded, the active pane has the
+		// current image which is preloaded anyway
+		let pinned: Vec<Arc<Image>> = self
+			.panes()
+			.into_iter()
+			.enumerate()
+			.filter(|&(pane, _)| pane != self.active_pane)
+			.filter_map(|(_, image)| image)
+			.collect();
+		let heading = self.travel.heading();
+
+		if (self.filter == Filter::All && self.shuffled.is_empty()) || self.images.is_empty() {
+			self.preload.update(
+				&self.images,
+				self.position,
+				&pinned,
+				heading,
+				only_if_starved,
+			);
+		} else {
+			let order = if self.shuffled.is_empty() {
+				&self.images
+			} else {
+				&self.shuffled
+			};
+
+			// The current image is always preloaded, even if it doesn't match
+			let current_image = &self.images[self.position];
+			let images: Vec<Arc<Image>> = order
+				.iter()
+				.filter(|image| *image == current_image || self.filter.matches(image))
+				.cloned()
+				.collect();
+
+			// Images added after shuffling aren't in the random order
+			match images.iter().position(|image| image == current_image) {
+				Some(current) => {
+					self.preload
+						.update(&images, current, &pinned, heading, only_if_starved);
+				}
+				None => self.preload.update(
+					&self.images,
+					self.position,
+					&pinned,
+					heading,
+					only_if_starved,
+				),
+			}
+		}
 	}
 
 	pub fn current(&self) -> Current {
 		if let Some(image) = self.images.get(self.position) {
+			let (position, total) = match self.filter {
+				Filter::All => (self.position + 1, self.images.len()),
+				filter => {
+					let count = |images: &[Arc<Image>]| {
+						images.iter().filter(|other| filter.matches(other)).count()
+					};
+					let before = count(&self.images[..self.position]);
+					let current = usize::from(filter.matches(image));
+					let after = count(&self.images[self.position + 1..]);
+
+					(before + current, before + current + after)
+				}
+			};
+
 			Current {
 				image: Some(image.clone()),
 				filename: image.filename.clone(),
-				position: self.position + 1,
-				total: self.images.len(),
+				position,
+				total,
 				mark: image.marked(),
+				filter: self.filter,
+				panes: self.panes(),
+				active_pane: self.active_pane,
+				index: self.position + 1,
+			}
+		} else {
+			Current {
+				filter: self.filter,
+				panes: self.panes(),
+				active_pane: self.active_pane,
+				..Current::default()
 			}
+		}
+	}
+
+	fn panes(&self) -> Vec<Option<Arc<Image>>> {
+		let image = self.images.get(self.position).cloned();
+
+		if self.panes.is_empty() {
+			vec![image]
 		} else {
-			Current::default()
+			let mut panes = self.panes.clone();
+
+			panes[self.active_pane] = image;
+			panes
+		}
+	}
+
+	/// Compare the current image and the images that follow it in `count`
+	/// panes, or stop comparing images if `count` is 1
+	pub fn compare(&mut self, count: usize) {
+		let mut position = Some(self.position);
+
+		self.panes.clear();
+		self.active_pane = 0;
+
+		if count > 1 {
+			for _ in 0..count {
+				self.panes
+					.push(position.and_then(|index| self.images.get(index).cloned()));
+				position = position.and_then(|index| self.find_forward(index + 1));
+			}
+		}
+
+		self.preload(false);
+	}
+
+	/// Change the active pane, which then shows the current image
+	pub fn navigate_pane(&mut self, action: Navigate) {
+		let Some(last) = self.panes.len().checked_sub(1) else {
+			return;
+		};
+		let active_pane = match action {
+			Navigate::First => 0,
+			Navigate::Previous => self.active_pane.saturating_sub(1),
+			Navigate::Next => min(self.active_pane + 1, last),
+			Navigate::Last => last,
+			Navigate::Backward(count) => self.active_pane.saturating_sub(count),
+			Navigate::Forward(count) => min(self.active_pane.saturating_add(count), last),
+			Navigate::Random => random_index(self.panes.len()),
+		};
+
+		self.panes[self.active_pane] = self.images.get(self.position).cloned();
+		self.active_pane = active_pane;
+
+		if let Some(image) = &self.panes[self.active_pane]
+			&& let Some(position) = self.images.iter().position(|other| other == image)
+		{
+			self.position = position;
 		}
+
+		self.preload(false);
 	}
 
 	pub fn position(&self) -> usize {
@@ -371,38 +1483,302 @@ impl State {
 			.map_or(0, |_| self.position + 1)
 	}
 
+	/// Find the first image at or after `from` that matches the filter
+	fn find_forward(&self, from: usize) -> Option<usize> {
+		self.images
+			.iter()
+			.enumerate()
+			.skip(from)
+			.find(|(_, image)| self.filter.matches(image))
+			.map(|(index, _)| index)
+	}
+
+	/// Find the last image before `until` that matches the filter
+	fn find_backward(&self, until: usize) -> Option<usize> {
+		self.images
+			.iter()
+			.enumerate()
+			.take(until)
+			.rev()
+			.find(|(_, image)| self.filter.matches(image))
+			.map(|(index, _)| index)
+	}
+
+	/// Find the image `count` images after the current image that matches
+	/// the filter, or the last one if there aren't enough images
+	fn find_forward_by(&self, count: usize) -> Option<usize> {
+		self.images
+			.iter()
+			.enumerate()
+			.skip(self.position + 1)
+			.filter(|(_, image)| self.filter.matches(image))
+			.take(count)
+			.last()
+			.map(|(index, _)| index)
+	}
+
+	/// Find the image `count` images before the current image that matches
+	/// the filter, or the first one if there aren't enough images
+	fn find_backward_by(&self, count: usize) -> Option<usize> {
+		self.images
+			.iter()
+			.enumerate()
+			.take(self.position)
+			.rev()
+			.filter(|(_, image)| self.filter.matches(image))
+			.take(count)
+			.last()
+			.map(|(index, _)| index)
+	}
+
+	/// Find a random image (other than the current image) that matches the
+	/// filter
+	fn find_random(&self) -> Option<usize> {
+		let matching: Vec<usize> = self
+			.images
+			.iter()
+			.enumerate()
+			.filter(|(index, image)| *index != self.position && self.filter.matches(image))
+			.map(|(index, _)| index)
+			.collect();
+
+		(!matching.is_empty()).then(|| matching[random_index(matching.len())])
+	}
+
 	pub fn navigate(&mut self, action: Navigate) {
-		match action {
-			Navigate::First => {
-				self.position = 0;
+		if let Some(position) = match action {
+			Navigate::First => self.find_forward(0),
+			Navigate::Previous => self.find_backward(self.position),
+			Navigate::Next => self.find_forward(self.position + 1),
+			Navigate::Last => self.find_backward(self.images.len()),
+			Navigate::Backward(count) => self.find_backward_by(count),
+			Navigate::Forward(count) => self.find_forward_by(count),
+			Navigate::Random => self.find_random(),
+		} {
+			self.position = position;
+		}
+
+		self.preload(false);
+	}
+
+	/// Move to an image by its number in the filtered list of images
+	pub fn go_to(&mut self, number: usize) {
+		let Some(index) = number.checked_sub(1) else {
+			return;
+		};
+
+		if let Some((position, _)) = self
+			.images
+			.iter()
+			.enumerate()
+			.filter(|(_, image)| self.filter.matches(image))
+			.nth(index)
+		{
+			self.position = position;
+		}
+
+		self.preload(false);
+	}
+
+	/// Move to the first image from the current image onwards (wrapping
+	/// around) that has a filename starting with `text`, or containing it,
+	/// ignoring case
+	pub fn find(&mut self, text: &str) {
+		let text = text.to_lowercase();
+
+		if text.is_empty() {
+			return;
+		}
+
+		let names: Vec<(usize, String)> = (self.position..self.images.len())
+			.chain(0..self.position)
+			.filter(|&index| self.filter.matches(&self.images[index]))
+			.map(|index| {
+				let filename = &self.images[index].filename;
+				let name = filename.file_name().unwrap_or(filename.as_os_str());
+
+				(index, name.to_string_lossy().to_lowercase())
+			})
+			.collect();
+
+		if let Some((position, _)) = names
+			.iter()
+			.find(|(_, name)| name.starts_with(&text))
+			.or_else(|| names.iter().find(|(_, name)| name.contains(&text)))
+		{
+			self.position = *position;
+		}
+
+		self.preload(false);
+	}
+
+	pub fn shuffle(&mut self, enable: bool) {
+		self.shuffled.clear();
+
+		if enable {
+			self.shuffled.clone_from(&self.images);
+			shuffle(&mut self.shuffled);
+
+			if let Some(current) = self.images.get(self.position)
+				&& let Some(index) = self.shuffled.iter().position(|image| image == current)
+			{
+				self.shuffled.swap(0, index);
 			}
+		}
+
+		self.preload(false);
+	}
+
+	/// Returns false if there are no more images
+	pub fn slideshow_next(&mut self, repeat: bool) -> bool {
+		let position = if self.shuffled.is_empty() {
+			self.find_forward(self.position + 1)
+				.or_else(|| if repeat { self.find_forward(0) } else { None })
+		} else {
+			self.find_shuffled(repeat)
+		};
+
+		if let Some(position) = position {
+			self.position = position;
+			self.preload(false);
+			true
+		} else {
+			false
+		}
+	}
 
-			Navigate::Previous => {
-				if self.position > 0 {
-					self.position -= 1;
+	/// Find the next image in the random order that matches the filter
+	fn find_shuffled(&mut self, repeat: bool) -> Option<usize> {
+		let current = self.images.get(self.position)?;
+		let start = self
+			.shuffled
+			.iter()
+			.position(|image| image == current)
+			.map_or(0, |index| index + 1);
+		let find = |shuffled: &[Arc<Image>]| {
+			shuffled.iter().find_map(|image| {
+				self.images
+					.iter()
+					.position(|other| other == image)
+					.filter(|_| self.filter.matches(image))
+			})
+		};
+
+		if let Some(position) = find(&self.shuffled[start..]) {
+			return Some(position);
+		}
+
+		if !repeat {
+			return None;
+		}
+
+		// Start again with a new order
+		self.shuffled.clone_from(&self.images);
+		shuffle(&mut self.shuffled);
+		find(&self.shuffled)
+	}
+
+	pub fn filter(&mut self, filter: Filter) {
+		self.filter = filter;
+
+		// Move to the nearest matching image if the current image doesn't
+		// match, preferring to move forward
+		if let Some(image) = self.images.get(self.position)
+			&& !self.filter.matches(image)
+			&& let Some(position) = self
+				.find_forward(self.position + 1)
+				.or_else(|| self.find_backward(self.position))
+		{
+			self.position = position;
+		}
+
+		self.preload(false);
+	}
+
+	pub fn select(&mut self, image: &Arc<Image>) {
+		if let Some(position) = self.images.iter().position(|other| other == image) {
+			self.position = position;
+		}
+
+		self.preload(false);
+	}
+
+	pub fn replace(&mut self, old: &Image, new: Arc<Image>) {
+		if let Some(index) = self.images.iter().position(|image| **image == *old) {
+			self.history.replace(old, &new);
+			self.changed_orientation(&new);
+
+			for pane in self.panes.iter_mut().flatten() {
+				if **pane == *old {
+					*pane = new.clone();
 				}
 			}
 
-			Navigate::Next => {
-				if !self.images.is_empty() && self.position < self.images.len() - 1 {
-					self.position += 1;
+			for other in &mut self.shuffled {
+				if **other == *old {
+					*other = new.clone();
 				}
 			}
 
-			Navigate::Last => {
-				if !self.images.is_empty() {
-					self.position = self.images.len() - 1;
-				}
+			self.images[index] = new;
+			self.preload(false);
+		}
+	}
+
+	/// Remove an image, moving to the next image if it's the current image
+	///
+	/// Returns the position that the image was removed from
+	pub fn remove(&mut self, image: &Arc<Image>) -> Option<usize> {
+		let position = self.images.iter().position(|other| other == image)?;
+
+		self.images.remove(position);
+		self.shuffled.retain(|other| other != image);
+		self.preload.remove(image);
+
+		if let Ok(filename) = path::absolute(&image.filename) {
+			self.orientations.remove(&filename);
+		}
+
+		for pane in &mut self.panes {
+			if pane.as_ref() == Some(image) {
+				*pane = None;
 			}
 		}
 
+		if position < self.position || self.position >= self.images.len() {
+			self.position = self.position.saturating_sub(1);
+		}
+
+		if let Some(current) = self.images.get(self.position)
+			&& !self.filter.matches(current)
+			&& let Some(other) = self
+				.find_forward(self.position + 1)
+				.or_else(|| self.find_backward(self.position))
+		{
+			self.position = other;
+		}
+
 		self.preload(false);
+		Some(position)
 	}
 
-	pub fn orientation(&mut self, add: Orientation) {
-		if let Some(image) = self.images.get(self.position) {
-			image.add_orientation(add);
-		}
+	/// Insert an image that was previously removed, and move to it
+	pub fn insert(&mut self, position: usize, image: Arc<Image>) {
+		let position = min(position, self.images.len());
+
+		self.changed_orientation(&image);
+		self.images.insert(position, image);
+		self.position = position;
+		self.preload(false);
+	}
+
+	/// Returns the image that was changed
+	pub fn orientation(&mut self, add: Orientation) -> Option<Arc<Image>> {
+		let image = self.images.get(self.position)?.clone();
+
+		image.add_orientation(add);
+		self.changed_orientation(&image);
+		Some(image)
 	}
 
 	pub fn shutdown(&self) {
@@ -410,10 +1786,92 @@ impl State {
 	}
 }
 
+/// Memory limit while the system is under memory pressure, which is half of
+/// the memory in use when there's pressure and grows back gradually when
+/// there isn't until it reaches the normal limit (`None`)
+fn pressure_limit(pressure: bool, usage: u64, current: Option<u64>, limit: u64) -> Option<u64> {
+	if pressure {
+		Some(usage / 2)
+	} else {
+		current.and_then(|current| {
+			let current = current.saturating_add(current.max(PRESSURE_RECOVERY));
+
+			(current < limit).then_some(current)
+		})
+	}
+}
+
+/// Half of the memory that is available, and no more than the previous fixed
+/// defaults
+///
+/// Blocking on I/O
+fn automatic_preload_memory() -> u64 {
+	let maximum: u64 = match size_of::<usize>() {
+		0..=4 => 1 << 30,
+		5.. => 20 << 30,
+	};
+	let limit = memory::available().map_or(maximum, |available| (available / 2).min(maximum));
+
+	debug!("Preload memory limit is {limit} bytes");
+	limit
+}
+
+/// A quarter of the memory that is available, and no more than the previous
+/// fixed defaults
+///
+/// Blocking on I/O
+fn automatic_full_memory() -> u64 {
+	let maximum: u64 = match size_of::<usize>() {
+		0..=4 => 512 << 20,
+		5.. => 4 << 30,
+	};
+	let limit = memory::available().map_or(maximum, |available| (available / 4).min(maximum));
+
+	debug!("Full resolution memory limit is {limit} bytes");
+	limit
+}
+
+/// Interleave two iterators, taking `weights` items from each of them in turn
+fn interleave_weighted<T>(
+	mut first: impl Iterator<Item = T>,
+	mut second: impl Iterator<Item = T>,
+	weights: (usize, usize),
+) -> impl Iterator<Item = T> {
+	let mut count = 0;
+
+	iter::from_fn(move || {
+		count = (count + 1) % (weights.0 + weights.1);
+
+		if count > 0 && count <= weights.0 {
+			first.next().or_else(|| second.next())
+		} else {
+			second.next().or_else(|| first.next())
+		}
+	})
+}
+
+/// Fisher-Yates shuffle
+fn shuffle(images: &mut [Arc<Image>]) {
+	for index in (1..images.len()).rev() {
+		images.swap(index, random_index(index + 1));
+	}
+}
+
+/// Random number from 0 to `len` (exclusive)
+fn random_index(len: usize) -> usize {
+	let end = i32::try_from(len).unwrap_or(i32::MAX);
+
+	usize::try_from(glib::random_int_range(0, end)).unwrap()
+}
+
 #[derive(Debug)]
 struct Preload {
 	capacity: usize,
 	memory_limit: u64,
+
+	/// Limit for images loaded at full resolution, separate from the limit
+	/// for preloading images at the display size
+	full_memory_limit: u64,
 	pool: ThreadPool,
 	state: Mutex<PreloadState>,
 	loading_required: Condvar,
@@ -423,29 +1881,87 @@ struct Preload {
 #[derive(Debug)]
 struct PreloadState {
 	priority: Option<Arc<Image>>,
+
+	/// Image to prioritise when navigation has stopped
+	deferred: Option<(Instant, Arc<Image>)>,
 	queue: VecDeque<Arc<Image>>,
 	load: HashSet<Arc<Image>>,
-	loading: HashSet<Arc<Image>>,
+	loading: HashMap<Arc<Image>, Cancel>,
 	loaded: HashSet<Arc<Image>>,
+
+	/// Expected memory use of the images that are being preloaded
+	memory_usage: u64,
+
+	/// Lower memory limit while the system is under memory pressure
+	pressure_limit: Option<u64>,
+
+	/// Size of the display that images are loaded to fit
+	display: Option<DimensionsU32>,
+	full: FullPreloadState,
+}
+
+/// Images that are loaded at full resolution because they're being drawn
+/// larger than the size that they were preloaded at, or because the pixel
+/// inspector needs their exact values
+#[derive(Debug, Default)]
+struct FullPreloadState {
+	/// Images that are being drawn
+	visible: Vec<Arc<Image>>,
+
+	/// Scale that the images are being drawn at, in device pixels, which is
+	/// at least 1 while the pixel inspector is enabled
+	scale: Option<f64>,
+
+	/// Lower memory limit while the system is under memory pressure
+	pressure_limit: Option<u64>,
+	queue: VecDeque<Arc<Image>>,
+	load: Vec<Arc<Image>>,
+	loading: HashMap<Arc<Image>, Cancel>,
+
+	/// Least recently used first
+	loaded: VecDeque<Arc<Image>>,
+}
+
+impl FullPreloadState {
+	/// Expected memory use of the images that are loaded or being loaded
+	fn memory_usage(&self) -> u64 {
+		self.loaded
+			.iter()
+			.chain(&self.queue)
+			.chain(self.loading.keys())
+			.map(|image| image.full_memory_required())
+			.sum()
+	}
 }
 
 impl PreloadState {
 	pub fn new(capacity: usize) -> Self {
 		Self {
 			priority: None,
+			deferred: None,
 			queue: VecDeque::with_capacity(capacity),
 			load: HashSet::with_capacity(capacity),
-			loading: HashSet::with_capacity(capacity),
+			loading: HashMap::with_capacity(capacity),
 			loaded: HashSet::with_capacity(capacity),
+			memory_usage: 0,
+			pressure_limit: None,
+			display: None,
+			full: FullPreloadState::default(),
 		}
 	}
 }
 
 impl Preload {
-	pub fn new(capacity: usize, memory_limit: u64, shutdown: Arc<AtomicBool>) -> Self {
+	pub fn new(
+		capacity: usize,
+		memory_limit: u64,
+		full_memory_limit: u64,
+		shutdown: Arc<AtomicBool>,
+	) -> Self {
 		Self {
 			capacity,
 			memory_limit,
+			full_memory_limit,
 			pool: threadpool::Builder::new().build(),
 			state: Mutex::new(PreloadState::new(capacity)),
 			loading_required: Condvar::new(),
@@ -479,12 +1995,23 @@ impl Preload {
 	}
 
 	fn notify(&self, state: &PreloadState) {
-		for _ in 0..min(state.queue.len(), self.pool.max_count()) {
+		for _ in 0..min(
+			state.queue.len() + state.full.queue.len(),
+			self.pool.max_count(),
+		) {
 			self.loading_required.notify_one();
 		}
 	}
 
-	pub fn update(&self, images: &[Arc<Image>], current: usize, only_if_starved: bool) {
+	/// Images that are `pinned` are always loaded, regardless of the limits
+	pub fn update(
+		&self,
+		images: &[Arc<Image>],
+		current: usize,
+		pinned: &[Arc<Image>],
+		heading: Heading,
+		only_if_starved: bool,
+	) {
 		if images.is_empty() || self.shutdown.load(atomic::Ordering::Acquire) {
 			return;
 		}
@@ -498,39 +2025,95 @@ impl Preload {
 		}
 
 		state.queue.clear();
+		state.priority = None;
+		state.deferred = None;
+
+		// When a key is held down, the current image and the next few images
+		// will only be displayed briefly so they're loaded last until
+		// navigation stops
+		if !images[current].loaded() {
+			if heading.passing > 0 {
+				state.deferred = Some((Instant::now() + SETTLE_TIME, images[current].clone()));
+			} else {
+				state.priority = Some(images[current].clone());
+			}
+		}
 
-		state.priority = if images[current].loaded() {
-			None
-		} else {
-			Some(images[current].clone())
-		};
+		state.full.visible = iter::once(&images[current])
+			.chain(pinned)
+			.cloned()
+			.collect();
 
-		// Preload images forward and backward
+		// Preload images forward and backward, more in the direction of travel
 		let forward = images.iter().skip(current + 1);
 		let backward = images.iter().rev().skip(images.len() - current);
-		let mut images =
-			itertools::chain(iter::once(&images[current]), interleave(forward, backward));
+		let passing: Vec<&Arc<Image>> = iter::once(&images[current])
+			.chain(match heading.direction {
+				Some(Direction::Forward) => forward.clone().take(heading.passing).collect(),
+				Some(Direction::Backward) => backward.clone().take(heading.passing).collect(),
+				None => Vec::new(),
+			})
+			.collect();
+		let images = itertools::chain!(
+			iter::once(&images[current]),
+			pinned,
+			interleave_weighted(
+				forward,
+				backward,
+				match heading.direction {
+					Some(Direction::Forward) => (TRAVEL_RATIO, 1),
+					Some(Direction::Backward) => (1, TRAVEL_RATIO),
+					None => (1, 1),
+				}
+			)
+		);
+		let mut later = Vec::new();
 		#[expect(clippy::mutable_key_type, reason = "Key is immutable")]
 		let mut load = HashSet::<Arc<Image>>::with_capacity(self.capacity);
 		let mut memory_usage: u64 = 0;
+		let memory_limit = state
+			.pressure_limit
+			.map_or(self.memory_limit, |limit| limit.min(self.memory_limit));
 
-		while load.len() < self.capacity {
-			if let Some(image) = images.next() {
-				if let Some(new_memory_usage) = memory_usage.checked_add(image.memory_required()) {
-					if new_memory_usage > self.memory_limit && !load.is_empty() {
-						break;
-					}
-					memory_usage = new_memory_usage;
-				} else {
+		for image in images {
+			// The current image is always loaded
+			let required = load.is_empty() || pinned.contains(image);
+
+			if load.contains(image) {
+				continue;
+			}
+
+			if load.len() >= self.capacity && !required {
+				break;
+			}
+
+			if let Some(new_memory_usage) =
+				memory_usage.checked_add(image.memory_required(state.display))
+			{
+				if new_memory_usage > memory_limit && !required {
 					break;
 				}
+				memory_usage = new_memory_usage;
+			} else if !required {
+				break;
+			}
 
-				load.insert(image.clone());
-				if !image.loaded() && !state.loading.contains(image) {
+			load.insert(image.clone());
+			if !image.loaded() && !state.loading.contains_key(image) {
+				if heading.passing > 0 && passing.contains(&image) {
+					later.push(image.clone());
+				} else {
 					state.queue.push_back(image.clone());
 				}
-			} else {
-				break;
+			}
+		}
+
+		state.queue.extend(later);
+
+		// Stop loading images that will not be preloaded
+		for (image, cancel) in &state.loading {
+			if !load.contains(image) {
+				cancel.cancel();
 			}
 		}
 
@@ -544,34 +2127,217 @@ impl Preload {
 			}
 		});
 		state.load = load;
+		state.memory_usage = memory_usage;
+		self.update_full(&mut state);
 
 		// Start background loading for images that are not loaded
 		self.notify(&state);
 	}
 
+	/// Shrink the preloaded images while the system is under memory pressure,
+	/// and allow them to grow back gradually afterwards
+	///
+	/// Returns true if the memory limit has changed
+	pub fn memory_pressure(&self, pressure: bool) -> bool {
+		let mut state = self.state.lock().unwrap();
+		let limit = pressure_limit(
+			pressure,
+			state.memory_usage,
+			state.pressure_limit,
+			self.memory_limit,
+		);
+		let full_limit = pressure_limit(
+			pressure,
+			state.full.memory_usage(),
+			state.full.pressure_limit,
+			self.full_memory_limit,
+		);
+
+		if limit == state.pressure_limit && full_limit == state.full.pressure_limit {
+			return false;
+		}
+
+		let describe = |value: Option<u64>| {
+			value.map_or_else(
+				|| "restored".to_string(),
+				|bytes| format!("reduced to {bytes} bytes"),
+			)
+		};
+
+		debug!(
+			"Memory pressure: preload limit {}, full resolution limit {}",
+			describe(limit),
+			describe(full_limit)
+		);
+
+		state.pressure_limit = limit;
+		state.full.pressure_limit = full_limit;
+		true
+	}
+
+	/// Images are loaded to fit the display, so that they use less memory
+	///
+	/// Returns true if the display size has changed
+	pub fn set_display(&self, display: DimensionsU32) -> bool {
+		self.state.lock().unwrap().display.replace(display) != Some(display)
+	}
+
+	/// Load the visible images at full resolution if they're drawn at a
+	/// `scale` that is larger than the size they were loaded at
+	pub fn zoom(&self, scale: Option<f64>) {
+		let mut state = self.state.lock().unwrap();
+
+		if state.full.scale != scale {
+			state.full.scale = scale;
+			self.update_full(&mut state);
+			self.notify(&state);
+		}
+	}
+
+	fn update_full(&self, state: &mut PreloadState) {
+		let full = &mut state.full;
+
+		full.load = match full.scale {
+			Some(scale) => full
+				.visible
+				.iter()
+				.filter(|image| image.needs_full(scale))
+				.cloned()
+				.collect(),
+			None => Vec::new(),
+		};
+
+		for (image, cancel) in &full.loading {
+			if !full.load.contains(image) {
+				cancel.cancel();
+			}
+		}
+
+		full.queue.clear();
+		for image in &full.load {
+			if let Some(index) = full.loaded.iter().position(|other| other == image) {
+				// Most recently used
+				let used = full.loaded.remove(index).unwrap();
+				full.loaded.push_back(used);
+			} else if !full.loading.contains_key(image) {
+				full.queue.push_back(image.clone());
+			}
+		}
+
+		self.limit_full(state);
+	}
+
+	/// Unload the least recently used images at full resolution until the
+	/// memory limit is met, except for the ones that are being drawn
+	fn limit_full(&self, state: &mut PreloadState) {
+		let full = &mut state.full;
+		let mut memory_usage = full.memory_usage();
+		let memory_limit = full.pressure_limit.map_or(self.full_memory_limit, |limit| {
+			limit.min(self.full_memory_limit)
+		});
+
+		while memory_usage > memory_limit
+			&& let Some(index) = full
+				.loaded
+				.iter()
+				.position(|image| !full.load.contains(image))
+		{
+			let image = full.loaded.remove(index).unwrap();
+
+			memory_usage = memory_usage.saturating_sub(image.full_memory_required());
+			image.unload_full();
+		}
+	}
+
+	/// Stop preloading an image that has been removed
+	pub fn remove(&self, image: &Arc<Image>) {
+		let mut state = self.state.lock().unwrap();
+
+		state.queue.retain(|other| other != image);
+		state.load.remove(image);
+		if let Some(cancel) = state.loading.get(image) {
+			cancel.cancel();
+		}
+		if state.loaded.remove(image) {
+			image.unload();
+		}
+
+		state.full.visible.retain(|other| other != image);
+		state.full.queue.retain(|other| other != image);
+		state.full.load.retain(|other| other != image);
+		if let Some(cancel) = state.full.loading.get(image) {
+			cancel.cancel();
+		}
+		if let Some(index) = state.full.loaded.iter().position(|other| other == image) {
+			state.full.loaded.remove(index);
+			image.unload_full();
+		}
+
+		if state.priority.as_ref() == Some(image) {
+			state.priority = None;
+			self.notify(&state);
+		}
+	}
+
 	fn load_one_or_wait(&self, files: &Files) {
 		let mut state = self.state.lock().unwrap();
 
+		if let Some((when, _)) = &state.deferred
+			&& *when <= Instant::now()
+		{
+			Self::settle(&mut state);
+		}
+
 		if let Some(priority) = &state.priority
-			&& state.loading.contains(priority)
+			&& state.loading.contains_key(priority)
 		{
 			// Wait until the priority image has been loaded
-			drop(self.loading_required.wait(state).unwrap());
+			self.wait(state);
+			return;
+		}
+
+		// Images that are being drawn at full resolution are more important
+		// than preloading other images
+		if state.priority.is_none()
+			&& let Some(image) = state.full.queue.pop_front()
+		{
+			let cancel = Cancel::default();
+
+			state.full.loading.insert(image.clone(), cancel.clone());
+			drop(state);
+
+			self.load_full(files, &image, &cancel);
 			return;
 		}
 
 		if let Some(image) = state.queue.pop_front() {
-			state.loading.insert(image.clone());
+			let cancel = Cancel::default();
+			let display = state.display;
+
+			state.loading.insert(image.clone(), cancel.clone());
 			drop(state);
 
-			image.load();
+			image.load(display, &cancel);
 
 			state = self.state.lock().unwrap();
 			state.loading.remove(&image);
 
-			if state.load.contains(&image) {
+			if !image.loaded() {
+				// Loading was cancelled, but the image has been required
+				// again since then
+				if state.load.contains(&image) {
+					state.queue.push_front(image);
+				}
+			} else if state.load.contains(&image) {
 				state.loaded.insert(image.clone());
 
+				// Now that the size that the image was loaded at is known, it
+				// may need to be loaded at full resolution
+				if state.full.visible.contains(&image) {
+					self.update_full(&mut state);
+					self.notify(&state);
+				}
+
 				trace!(
 					"Loaded {} image{}",
 					state.loaded.len(),
@@ -595,7 +2361,61 @@ impl Preload {
 				image.unload();
 			}
 		} else {
-			drop(self.loading_required.wait(state).unwrap());
+			self.wait(state);
+		}
+	}
+
+	fn load_full(&self, files: &Files, image: &Arc<Image>, cancel: &Cancel) {
+		image.load_full(cancel);
+
+		let mut state = self.state.lock().unwrap();
+
+		state.full.loading.remove(image);
+
+		if !image.full_loaded() {
+			// Loading was cancelled, but the image has been required again
+			// since then
+			if state.full.load.contains(image) {
+				state.full.queue.push_front(image.clone());
+			}
+		} else if state.full.load.contains(image) {
+			state.full.loaded.push_back(image.clone());
+			self.limit_full(&mut state);
+
+			// Release preload mutex before acquiring the state mutex
+			drop(state);
+			files.loaded(image);
+		} else {
+			image.unload_full();
+		}
+	}
+
+	/// Navigation has stopped, so the current image is now the priority
+	fn settle(state: &mut PreloadState) {
+		let Some((_, image)) = state.deferred.take() else {
+			return;
+		};
+
+		if image.loaded() || !state.load.contains(&image) {
+			return;
+		}
+
+		if !state.loading.contains_key(&image) {
+			state.queue.retain(|other| *other != image);
+			state.queue.push_front(image.clone());
+		}
+		state.priority = Some(image);
+	}
+
+	/// Wait until there's more to do, or until the deferred image needs to be
+	/// prioritised
+	fn wait(&self, state: MutexGuard<PreloadState>) {
+		match &state.deferred {
+			Some((when, _)) => {
+				let timeout = when.saturating_duration_since(Instant::now());
+				drop(self.loading_required.wait_timeout(state, timeout).unwrap());
+			}
+			None => drop(self.loading_required.wait(state).unwrap()),
 		}
 	}
 
@@ -605,6 +2425,11 @@ impl Preload {
 
 			state.queue.clear();
 			state.load.clear();
+			state.full.queue.clear();
+			state.full.load.clear();
+			for cancel in state.loading.values().chain(state.full.loading.values()) {
+				cancel.cancel();
+			}
 			self.loading_required.notify_all();
 		}
 
@@ -619,6 +2444,11 @@ impl Preload {
 				image.unload();
 			}
 			state.loaded.clear();
+
+			for image in &state.full.loaded {
+				image.unload_full();
+			}
+			state.full.loaded.clear();
 		});
 	}
 }
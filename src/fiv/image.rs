@@ -16,37 +16,71 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use super::codecs::{Codec, CodecMetadata, Codecs};
+use super::codecs::{Cancel, Codec, CodecMetadata, CodecPrimary, Codecs};
 use super::numeric::{DimensionsF64, DimensionsU32, PointF64, Xi32, Xu32, Yi32, Yu32};
-use anyhow::{Error, anyhow, ensure};
+use super::util::mapped_file::{FileIdentity, MappedFile};
+use anyhow::{Error, anyhow, bail, ensure};
 use bytemuck::{cast_slice, cast_slice_mut};
-use core::slice::IterMut;
 use gtk::cairo;
-use log::{error, trace};
-use memmap2::{Advice, Mmap, UncheckedAdvice};
+use log::{debug, error, trace, warn};
 use pathdiff::diff_paths;
 use std::cell::RefCell;
-use std::fs::{File, read_link, remove_file};
+use std::cmp::min;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, read_link, remove_file};
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::ops::AddAssign;
+use std::iter;
+use std::mem;
+use std::ops::{AddAssign, Neg};
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::{Arc, Mutex, atomic};
 use std::time::Instant;
 
 #[derive(Debug)]
 pub struct Image {
 	id: usize,
+
+	/// Position of the file in the list of filenames, so that images can be
+	/// kept in order when they're opened out of order
+	pub order: usize,
 	pub filename: PathBuf,
-	map: Mmap,
-	codec: Codecs,
+
+	/// Version of the file that the image was opened from
+	identity: Option<FileIdentity>,
+
+	/// The file was changed after the image was opened, so it needs to be
+	/// opened again
+	changed: AtomicBool,
+
+	/// Codecs to try in turn, or empty if the image couldn't be opened
+	codecs: Vec<Codecs>,
+
+	/// Index of the codec that last succeeded, so that the codecs before it
+	/// aren't tried again
+	codec: AtomicUsize,
 	pub metadata: CodecMetadata,
+
+	/// Reason that the image couldn't be opened or decoded
+	failure: Mutex<Option<Failure>>,
+
+	/// The image has been decoded (or couldn't be opened), so whether it has
+	/// failed is known
+	decoded: AtomicBool,
+
+	/// The image was only partly decoded the last time that it was loaded
+	partial: Mutex<Option<Partial>>,
 	mark_link: Option<Link>,
 	marked: Mutex<Option<bool>>,
 	data: Mutex<Option<ImageData>>,
+
+	/// Image at full resolution, for when `data` has been loaded at a reduced
+	/// size and the image is being drawn larger than that
+	full: Mutex<Option<ImageData>>,
 	orientation: Mutex<Orientation>,
 }
 
@@ -66,6 +100,17 @@ impl Hash for Image {
 
 pub type Pixel = u32;
 
+/// Stop creating mipmaps when the image is this small
+const MIPMAP_MIN_SIZE: u32 = 256;
+
+/// Dimensions to draw images that couldn't be opened at, so that there's
+/// somewhere to show the error
+const FAILED_DIMENSIONS: (u32, u32) = (640, 480);
+
+/// Dimensions to decode images at when checking that they can be decoded,
+/// which is the smallest size that each codec can decode them at
+const CHECK_DIMENSIONS: (u32, u32) = (1, 1);
+
 #[derive(derive_more::Debug)]
 pub struct ImageData {
 	#[debug("{:?}", data.as_ref().map(|x| Some(x.len())))]
@@ -74,6 +119,9 @@ pub struct ImageData {
 	width: Xi32,
 	height: Yi32,
 	stride: i32,
+
+	/// Reduced copies of the image, each half the size of the previous one
+	mipmaps: Vec<ImageData>,
 }
 
 #[derive(derive_more::Debug)]
@@ -86,7 +134,25 @@ pub struct ImageDataBuilder {
 	pub stride: i32,
 }
 
-#[derive(Debug)]
+/// Reason that an image couldn't be opened or decoded
+#[derive(Debug, Clone)]
+pub struct Failure {
+	/// Name of the codec, if the type of image was recognised
+	pub codec: Option<&'static str>,
+	pub error: String,
+}
+
+/// Image that was only partly decoded because the file is truncated or
+/// corrupt
+#[derive(Debug, Clone)]
+pub struct Partial {
+	/// Rows from the top of the image that were decoded, the rest of the
+	/// image is missing
+	pub rows: Yu32,
+	pub warning: String,
+}
+
+#[derive(Debug, Clone)]
 struct Link {
 	name: PathBuf,
 	target: PathBuf,
@@ -126,37 +192,162 @@ impl Image {
 	/// Blocking on CPU, I/O
 	pub fn new<P: AsRef<Path>>(
 		canonical_mark_directory: Option<&PathBuf>,
+		order: usize,
 		filename: P,
 	) -> Result<Arc<super::Image>, Error> {
-		static COUNTER: AtomicUsize = AtomicUsize::new(0);
-		let map = unsafe { Mmap::map(&File::open(filename.as_ref())?)? };
-		map.advise(Advice::DontDump)?;
-		let codec = Codecs::new(&map)?;
-		let metadata = codec.metadata(&map)?;
-		let orientation = metadata.orientation;
 		let path = filename.as_ref().to_path_buf();
 		let mark_link = mark_link(canonical_mark_directory, &path);
 
-		ensure!(
-			metadata.dimensions.non_zero(),
-			"Image dimensions are zero: {}",
-			metadata.dimensions
-		);
+		Self::open(order, path, mark_link)
+	}
+
+	/// The file is only mapped while it's being used, so that there aren't
+	/// too many mappings for large numbers of files
+	///
+	/// Images that can't be opened are kept so that the error can be shown,
+	/// but files that aren't images return an error (files that can't be
+	/// read are only images if they have an image extension)
+	///
+	/// Blocking on CPU, I/O
+	fn open(
+		order: usize,
+		path: PathBuf,
+		mark_link: Option<Link>,
+	) -> Result<Arc<super::Image>, Error> {
+		let file = match MappedFile::open(&path) {
+			Ok(file) => file,
+			Err(err) => {
+				let codecs = Codecs::by_filename(&path);
+
+				if codecs.is_empty() {
+					return Err(err);
+				}
+				return Ok(Self::failed(order, path, mark_link, codecs.first(), &err));
+			}
+		};
+		let mut codecs = Codecs::candidates(&file, &path)?;
+		let metadata = Self::metadata(&file, &path, &codecs).and_then(|(index, metadata)| {
+			ensure!(!file.changed(), "File changed while it was being opened");
+			ensure!(
+				metadata.dimensions.non_zero(),
+				"Image dimensions are zero: {}",
+				metadata.dimensions
+			);
+			Ok((index, metadata))
+		});
+
+		Ok(match metadata {
+			Ok((index, metadata)) => {
+				codecs.drain(..index);
+				Self::build(
+					order,
+					path,
+					mark_link,
+					Some(file.identity()),
+					codecs,
+					metadata,
+					None,
+				)
+			}
+			Err(err) => Self::failed(order, path, mark_link, codecs.first(), &err),
+		})
+	}
+
+	/// Try each codec in turn, returning the index of the first one that
+	/// succeeds or the error from the first one if they all fail
+	///
+	/// Blocking on CPU, I/O
+	fn metadata(
+		file: &[u8],
+		path: &Path,
+		codecs: &[Codecs],
+	) -> Result<(usize, CodecMetadata), Error> {
+		let mut first_err = None;
+
+		for (index, codec) in codecs.iter().enumerate() {
+			match codec.metadata(file) {
+				Ok(metadata) => return Ok((index, metadata)),
+				Err(err) => {
+					debug!("{}: {codec:?}: {err}", path.display());
+					first_err.get_or_insert(err);
+				}
+			}
+		}
+
+		Err(first_err.unwrap_or_else(|| anyhow!("No codecs")))
+	}
+
+	fn failed(
+		order: usize,
+		path: PathBuf,
+		mark_link: Option<Link>,
+		codec: Option<&Codecs>,
+		err: &Error,
+	) -> Arc<super::Image> {
+		error!("{}: {err}", path.display());
+
+		let metadata = CodecMetadata {
+			dimensions: DimensionsU32::from(FAILED_DIMENSIONS),
+			orientation: Orientation::default(),
+			af_points: None,
+			bit_depth: None,
+		};
+		let failure = Failure {
+			codec: codec.map(<&'static str>::from),
+			error: err.to_string(),
+		};
+
+		Self::build(
+			order,
+			path,
+			mark_link,
+			None,
+			Vec::new(),
+			metadata,
+			Some(failure),
+		)
+	}
+
+	fn build(
+		order: usize,
+		path: PathBuf,
+		mark_link: Option<Link>,
+		identity: Option<FileIdentity>,
+		codecs: Vec<Codecs>,
+		metadata: CodecMetadata,
+		failure: Option<Failure>,
+	) -> Arc<super::Image> {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+		let orientation = metadata.orientation;
 
 		let image = Arc::new(Image {
 			id: COUNTER.fetch_add(1, atomic::Ordering::Relaxed),
+			order,
 			filename: path,
-			map,
-			codec,
+			identity,
+			changed: AtomicBool::new(false),
+			codecs,
+			codec: AtomicUsize::new(0),
 			metadata,
+			decoded: AtomicBool::new(failure.is_some()),
+			failure: Mutex::new(failure),
+			partial: Mutex::new(None),
 			mark_link,
 			marked: Mutex::new(None),
 			data: Mutex::new(None),
+			full: Mutex::new(None),
 			orientation: Mutex::new(orientation),
 		});
 
 		image.refresh_mark();
-		Ok(image)
+		image
+	}
+
+	/// Open the file again as a new image, to pick up changes to the file
+	///
+	/// Blocking on CPU, I/O
+	pub fn reload(&self) -> Result<Arc<super::Image>, Error> {
+		Self::open(self.order, self.filename.clone(), self.mark_link.clone())
 	}
 
 	pub fn width(&self) -> Xu32 {
@@ -192,6 +383,30 @@ impl Image {
 		*marked = self.read_mark_link();
 	}
 
+	/// Remove the mark link when the file is moved to the trash, so that it
+	/// isn't left pointing at nothing, but keep the mark so that the link can
+	/// be restored with the file
+	///
+	/// Blocking on I/O
+	pub fn trash_mark(&self) {
+		let marked = self.marked.lock().unwrap();
+
+		if *marked == Some(true) {
+			self.write_mark_link(false, false);
+		}
+	}
+
+	/// Create the mark link again when the file is restored from the trash
+	///
+	/// Blocking on I/O
+	pub fn restore_mark(&self) {
+		let marked = self.marked.lock().unwrap();
+
+		if *marked == Some(true) {
+			self.write_mark_link(true, false);
+		}
+	}
+
 	/// Blocking on I/O
 	fn read_mark_link(&self) -> Option<bool> {
 		self.mark_link
@@ -234,30 +449,91 @@ impl Image {
 		}
 	}
 
-	pub fn memory_required(&self) -> u64 {
-		ImageData::memory_required(self.metadata.dimensions)
+	/// Memory required to load the image at a size that fits `display`
+	///
+	/// This is the memory that's kept after the image has been loaded. Codecs
+	/// that can't decode at a reduced size (see `Codec::decode_dimensions()`)
+	/// temporarily need memory for the full size image and its mipmaps while
+	/// it's being loaded, which isn't included because it's only needed for
+	/// the images that are being loaded at the time.
+	pub fn memory_required(&self, display: Option<DimensionsU32>) -> u64 {
+		ImageData::memory_required(self.preview_dimensions(display))
 	}
 
-	/// Blocking on CPU, I/O
-	pub fn load(&self) {
-		let begin = Instant::now();
+	/// Memory required to load the image at full resolution
+	pub fn full_memory_required(&self) -> u64 {
+		ImageData::calculate_allocation(self.metadata.dimensions)
+			.map_or(0, |allocation| allocation.memory)
+	}
 
-		self.map.advise(Advice::WillNeed).unwrap();
+	/// Dimensions that the image will be loaded at to fit `display`
+	fn preview_dimensions(&self, display: Option<DimensionsU32>) -> DimensionsU32 {
+		let minimum = self.minimum_dimensions(display);
 
-		let image_data = Some(match self.codec.primary(&self.map, &self.metadata) {
-			Ok(primary) => primary.image_data,
-			Err(err) => {
-				error!("{}: {err}", self.filename.display());
-				ImageData::failed()
-			}
-		});
+		let dimensions = match self.codec() {
+			Some(codec) => codec.decode_dimensions(&self.metadata, minimum),
+			None => self.metadata.dimensions,
+		};
 
-		unsafe {
-			self.map
-				.unchecked_advise(UncheckedAdvice::DontNeed)
-				.unwrap();
+		ImageData::reduced_dimensions(dimensions, minimum)
+	}
+
+	/// Smallest dimensions that the image can be loaded at for it to fit
+	/// `display` without being enlarged, or the full size if the display size
+	/// isn't known
+	fn minimum_dimensions(&self, display: Option<DimensionsU32>) -> DimensionsU32 {
+		let Some(display) = display else {
+			return self.metadata.dimensions;
+		};
+		let display = match self.orientation().rotate {
+			Rotate::Rotate0 | Rotate::Rotate180 => display,
+			Rotate::Rotate90 | Rotate::Rotate270 => display.rotate90(),
+		};
+
+		if display.covers(self.metadata.dimensions) {
+			return self.metadata.dimensions;
 		}
 
+		let width = u64::from(u32::from(self.width()));
+		let height = u64::from(u32::from(self.height()));
+		let display_width = u64::from(u32::from(display.width));
+		let display_height = u64::from(u32::from(display.height));
+		let (width, height) = if display_width * height <= display_height * width {
+			(display_width, (height * display_width).div_ceil(width))
+		} else {
+			((width * display_height).div_ceil(height), display_height)
+		};
+
+		DimensionsU32::from((
+			u32::try_from(width).unwrap_or(u32::MAX),
+			u32::try_from(height).unwrap_or(u32::MAX),
+		))
+	}
+
+	/// Load the image at a reduced size that fits `display`, if possible
+	///
+	/// Loading stops early without changing the state of the image if
+	/// `cancel` is set
+	///
+	/// Blocking on CPU, I/O
+	pub fn load(&self, display: Option<DimensionsU32>, cancel: &Cancel) {
+		let begin = Instant::now();
+		let minimum = self.minimum_dimensions(display);
+		let image_data = self.decode(minimum, cancel).map(|mut image_data| {
+			image_data.build_mipmaps();
+			image_data.discard_larger(minimum);
+			image_data
+		});
+
+		let Some(image_data) = image_data else {
+			trace!(
+				"{}: Cancelled after {:?}",
+				self.filename.display(),
+				begin.elapsed()
+			);
+			return;
+		};
+
 		let mut data = self.data.lock().unwrap();
 
 		trace!(
@@ -266,13 +542,186 @@ impl Image {
 			begin.elapsed()
 		);
 
-		*data = image_data;
+		*data = Some(image_data);
+	}
+
+	/// Load the image at full resolution, for drawing it larger than the
+	/// size that it was loaded at
+	///
+	/// Loading stops early without changing the state of the image if
+	/// `cancel` is set
+	///
+	/// Blocking on CPU, I/O
+	pub fn load_full(&self, cancel: &Cancel) {
+		let begin = Instant::now();
+		let Some(image_data) = self.decode(self.metadata.dimensions, cancel) else {
+			trace!(
+				"{}: Cancelled full resolution after {:?}",
+				self.filename.display(),
+				begin.elapsed()
+			);
+			return;
+		};
+
+		let mut full = self.full.lock().unwrap();
+
+		trace!(
+			"{}: Loaded full resolution in {:?}",
+			self.filename.display(),
+			begin.elapsed()
+		);
+
+		*full = Some(image_data);
+	}
+
+	/// Decode the image without keeping it, if it hasn't been decoded
+	/// before, so that it's known whether it can be decoded
+	///
+	/// Blocking on CPU, I/O
+	pub fn check(&self, cancel: &Cancel) {
+		if !self.decoded() {
+			self.decode(
+				self.minimum_dimensions(Some(DimensionsU32::from(CHECK_DIMENSIONS))),
+				cancel,
+			);
+		}
+	}
+
+	/// Returns `None` if decoding was cancelled
+	///
+	/// Blocking on CPU, I/O
+	fn decode(&self, minimum: DimensionsU32, cancel: &Cancel) -> Option<ImageData> {
+		// The reason that it couldn't be opened is already known
+		let Some(codec) = self.codec() else {
+			return Some(ImageData::failed());
+		};
+
+		let result = MappedFile::open(&self.filename).and_then(|file| {
+			if Some(file.identity()) != self.identity {
+				self.changed.store(true, atomic::Ordering::Release);
+				bail!("File changed since it was opened");
+			}
+
+			file.will_need();
+
+			let result = self.primary(&file, minimum, cancel);
+
+			file.dont_need();
+
+			if file.changed() {
+				self.changed.store(true, atomic::Ordering::Release);
+				bail!("File changed while it was being read");
+			}
+			result
+		});
+
+		match result {
+			Ok(primary) => {
+				let partial = primary.partial.map(|partial| {
+					warn!("{}: {}", self.filename.display(), partial.warning);
+
+					// Convert from the size that the image was decoded at
+					let decoded_height = u64::try_from(i32::from(primary.image_data.height))
+						.unwrap_or(1)
+						.max(1);
+					let rows = u64::from(u32::from(partial.rows))
+						* u64::from(u32::from(self.height()))
+						/ decoded_height;
+
+					Partial {
+						rows: u32::try_from(rows).unwrap_or(u32::MAX).into(),
+						..partial
+					}
+				});
+
+				*self.failure.lock().unwrap() = None;
+				*self.partial.lock().unwrap() = partial;
+				self.decoded.store(true, atomic::Ordering::Release);
+				Some(primary.image_data)
+			}
+			Err(_) if cancel.cancelled() => None,
+			Err(err) => {
+				error!("{}: {err}", self.filename.display());
+
+				*self.failure.lock().unwrap() = Some(Failure {
+					codec: Some(codec.into()),
+					error: err.to_string(),
+				});
+				self.decoded.store(true, atomic::Ordering::Release);
+				Some(ImageData::failed())
+			}
+		}
+	}
+
+	/// Try each codec in turn, starting with the one that succeeded last
+	/// time, returning the error from that one if they all fail
+	///
+	/// Blocking on CPU, I/O
+	fn primary(
+		&self,
+		file: &[u8],
+		minimum: DimensionsU32,
+		cancel: &Cancel,
+	) -> Result<CodecPrimary, Error> {
+		let start = self.codec.load(atomic::Ordering::Relaxed);
+		let mut first_err = None;
+
+		for (index, codec) in self.codecs.iter().enumerate().skip(start) {
+			match codec
+				.primary(file, &self.metadata, minimum, cancel)
+				.and_then(|primary| cancel.check().map(|()| primary))
+			{
+				Ok(primary) => {
+					if index != start {
+						debug!("{}: Decoded with {codec:?}", self.filename.display());
+						self.codec.store(index, atomic::Ordering::Relaxed);
+					}
+					return Ok(primary);
+				}
+				Err(err) if cancel.cancelled() => return Err(err),
+				Err(err) => {
+					debug!("{}: {codec:?}: {err}", self.filename.display());
+					first_err.get_or_insert(err);
+				}
+			}
+		}
+
+		Err(first_err.unwrap_or_else(|| anyhow!("No codecs")))
+	}
+
+	/// The codec that succeeded last time, or `None` if the image couldn't be
+	/// opened
+	fn codec(&self) -> Option<&Codecs> {
+		self.codecs.get(self.codec.load(atomic::Ordering::Relaxed))
+	}
+
+	/// Returns the part of the image that was decoded if the file is
+	/// truncated or corrupt, the last time that it was loaded
+	pub fn partial(&self) -> Option<Partial> {
+		self.partial.lock().unwrap().clone()
+	}
+
+	/// Returns the reason that the image couldn't be opened, or the reason
+	/// that it couldn't be decoded the last time that it was loaded
+	pub fn failure(&self) -> Option<Failure> {
+		self.failure.lock().unwrap().clone()
+	}
+
+	/// The image has been decoded at least once, or it couldn't be opened
+	pub fn decoded(&self) -> bool {
+		self.decoded.load(atomic::Ordering::Acquire)
 	}
 
 	pub fn loaded(&self) -> bool {
 		self.data.lock().unwrap().is_some()
 	}
 
+	/// The file has been changed since the image was opened, so the image
+	/// failed to load
+	pub fn changed(&self) -> bool {
+		self.changed.load(atomic::Ordering::Acquire)
+	}
+
 	pub fn unload(&self) {
 		let mut data = self.data.lock().unwrap();
 
@@ -281,10 +730,38 @@ impl Image {
 		*data = None;
 	}
 
+	pub fn full_loaded(&self) -> bool {
+		self.full.lock().unwrap().is_some()
+	}
+
+	pub fn unload_full(&self) {
+		let mut full = self.full.lock().unwrap();
+
+		trace!("{}: Unloaded full resolution", self.filename.display());
+
+		*full = None;
+	}
+
+	/// The image was loaded at a reduced size, so it needs to be loaded at
+	/// full resolution to be drawn at `scale`
+	pub fn needs_full(&self, scale: f64) -> bool {
+		let width = f64::from(u32::from(self.width())) * scale;
+
+		self.data
+			.lock()
+			.unwrap()
+			.as_ref()
+			.is_some_and(|data| data.reduced_below(width))
+	}
+
 	pub fn orientation(&self) -> Orientation {
 		*self.orientation.lock().unwrap()
 	}
 
+	pub fn set_orientation(&self, orientation: Orientation) {
+		*self.orientation.lock().unwrap() = orientation;
+	}
+
 	pub fn add_orientation(&self, add: Orientation) -> Orientation {
 		let mut orientation = self.orientation.lock().unwrap();
 
@@ -292,13 +769,120 @@ impl Image {
 		*orientation
 	}
 
+	/// Modify the file so that its orientation matches the current orientation,
+	/// returning true if the file was changed. The file is replaced atomically.
+	///
+	/// Blocking on CPU, I/O
+	pub fn save_orientation(&self) -> Result<bool, Error> {
+		let orientation = self.orientation();
+
+		if orientation == self.metadata.orientation {
+			return Ok(false);
+		}
+
+		let Some(codec) = self.codec() else {
+			bail!("Image could not be opened");
+		};
+		let file = MappedFile::open(&self.filename)?;
+
+		ensure!(
+			Some(file.identity()) == self.identity,
+			"File changed since it was opened"
+		);
+
+		let temp_filename = temp_filename(&self.filename)?;
+		let result = fs::copy(&self.filename, &temp_filename)
+			.map_err(Error::from)
+			.and_then(|_| codec.save_orientation(&file, orientation, &temp_filename))
+			.and_then(|()| {
+				ensure!(!file.changed(), "File changed while it was being read");
+				Ok(())
+			})
+			.and_then(|()| {
+				File::open(&temp_filename)?.sync_all()?;
+				fs::rename(&temp_filename, &self.filename)?;
+				Ok(())
+			});
+
+		if result.is_err() {
+			remove_file(&temp_filename).unwrap_or_else(|err| {
+				if err.kind() != io::ErrorKind::NotFound {
+					error!("{}: {err}", temp_filename.display());
+				}
+			});
+		}
+
+		result.map(|()| true)
+	}
+
+	/// Value of a pixel in the decoded image, if it's loaded, and whether it's
+	/// exact
+	///
+	/// The value is only approximate if the image hasn't been loaded at full
+	/// resolution, because it's from the reduced image
+	pub fn pixel(&self, x: Xu32, y: Yu32) -> Option<(Pixel, bool)> {
+		if let Some(full) = self.full.lock().unwrap().as_ref()
+			&& full.data.is_some()
+		{
+			return full.pixel(x, y).map(|pixel| (pixel, true));
+		}
+
+		let data = self.data.lock().unwrap();
+		let data = data.as_ref()?;
+		let rescale = |value: u32, to: i32, from: u32| {
+			u32::try_from(u64::from(value) * u64::try_from(to).ok()? / u64::from(from)).ok()
+		};
+
+		let exact = !data.reduced_below(f64::from(u32::from(self.width())));
+
+		data.pixel(
+			rescale(x.into(), data.width.into(), self.width().into())?.into(),
+			rescale(y.into(), data.height.into(), self.height().into())?.into(),
+		)
+		.map(|pixel| (pixel, exact))
+	}
+
 	/// Blocks other accesses to image data and load/unload/loaded state
-	pub fn with_surface<F: FnOnce(Option<&cairo::ImageSurface>, bool)>(&self, func: F) {
+	///
+	/// The surface may be smaller than the image if it's going to be drawn at
+	/// a reduced `scale`, or if the image hasn't been loaded at full
+	/// resolution yet
+	///
+	/// Returns true if the surface is smaller than required for `scale`
+	/// because the image hasn't been loaded at full resolution
+	pub fn with_surface<F: FnOnce(Option<&cairo::ImageSurface>, bool)>(
+		&self,
+		scale: f64,
+		func: F,
+	) -> bool {
+		let width = f64::from(u32::from(self.width())) * scale;
 		let mut data = self.data.lock().unwrap();
+		let mut full = self.full.lock().unwrap();
+
+		match (&mut *data, &mut *full) {
+			(Some(data), Some(full)) if data.reduced_below(width) && full.data.is_some() => {
+				full.with_surface(width, func);
+				false
+			}
+			(Some(data), full) => {
+				let reduced = full.is_none() && data.reduced_below(width);
 
-		match &mut *data {
-			Some(data) => data.with_surface(func),
-			None => func(None, false),
+				data.with_surface(width, func);
+				reduced
+			}
+			(None, _) => {
+				func(None, false);
+				false
+			}
+		}
+	}
+}
+
+impl fmt::Display for Failure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.codec {
+			Some(codec) => write!(f, "{codec}: {}", self.error),
+			None => f.write_str(&self.error),
 		}
 	}
 }
@@ -332,6 +916,19 @@ fn mark_link(mark_directory: Option<&PathBuf>, filename: &Path) -> Option<Link>
 	}
 }
 
+/// Temporary file in the same directory, so that it can be renamed over the
+/// original file
+fn temp_filename(filename: &Path) -> Result<PathBuf, Error> {
+	let basename = filename
+		.file_name()
+		.ok_or_else(|| anyhow!("Invalid filename: {}", filename.display()))?;
+	let mut temp_basename = OsString::from(".");
+
+	temp_basename.push(basename);
+	temp_basename.push(format!(".{}.tmp", std::process::id()));
+	Ok(filename.with_file_name(temp_basename))
+}
+
 impl AddAssign<Orientation> for Orientation {
 	fn add_assign(&mut self, rhs: Orientation) {
 		self.rotate += rhs.rotate;
@@ -363,6 +960,27 @@ impl AddAssign<Rotate> for Rotate {
 	}
 }
 
+impl Neg for Orientation {
+	type Output = Orientation;
+
+	fn neg(self) -> Self::Output {
+		Orientation::new(-self.rotate, self.horizontal_flip)
+	}
+}
+
+impl Neg for Rotate {
+	type Output = Rotate;
+
+	fn neg(self) -> Self::Output {
+		match self {
+			Rotate::Rotate0 => Rotate::Rotate0,
+			Rotate::Rotate90 => Rotate::Rotate270,
+			Rotate::Rotate180 => Rotate::Rotate180,
+			Rotate::Rotate270 => Rotate::Rotate90,
+		}
+	}
+}
+
 impl From<image::metadata::Orientation> for Orientation {
 	fn from(orientation: image::metadata::Orientation) -> Self {
 		match orientation {
@@ -401,12 +1019,30 @@ impl From<Option<&rexiv2::Metadata>> for Orientation {
 	}
 }
 
-impl ImageDataBuilder {
-	pub fn iter_mut(&mut self) -> IterMut<'_, Pixel> {
-		self.buffer.iter_mut()
+impl From<Orientation> for rexiv2::Orientation {
+	fn from(orientation: Orientation) -> Self {
+		match (orientation.rotate, orientation.horizontal_flip) {
+			(Rotate::Rotate0, false) => rexiv2::Orientation::Normal,
+			(Rotate::Rotate0, true) => rexiv2::Orientation::HorizontalFlip,
+			(Rotate::Rotate180, false) => rexiv2::Orientation::Rotate180,
+			(Rotate::Rotate180, true) => rexiv2::Orientation::VerticalFlip,
+			(Rotate::Rotate270, true) => rexiv2::Orientation::Rotate90HorizontalFlip,
+			(Rotate::Rotate90, false) => rexiv2::Orientation::Rotate90,
+			(Rotate::Rotate90, true) => rexiv2::Orientation::Rotate90VerticalFlip,
+			(Rotate::Rotate270, false) => rexiv2::Orientation::Rotate270,
+		}
 	}
 }
 
+/// Average of each channel of 4 XRGB pixels, calculating the red and blue
+/// channels together because the sums can't overflow into each other
+fn average_pixels(pixels: [Pixel; 4]) -> Pixel {
+	let red_blue: Pixel = pixels.iter().map(|pixel| pixel & 0x00ff_00ff).sum();
+	let green: Pixel = pixels.iter().map(|pixel| pixel & 0x0000_ff00).sum();
+
+	(((red_blue + 0x0002_0002) >> 2) & 0x00ff_00ff) | (((green + 0x0000_0200) >> 2) & 0x0000_ff00)
+}
+
 impl AsRef<[u8]> for ImageDataBuilder {
 	fn as_ref(&self) -> &[u8] {
 		cast_slice::<Pixel, u8>(self.buffer.as_ref())
@@ -463,6 +1099,7 @@ impl From<ImageDataBuilder> for ImageData {
 			width: builder.width,
 			height: builder.height,
 			stride: builder.stride,
+			mipmaps: Vec::new(),
 		}
 	}
 }
@@ -516,8 +1153,98 @@ impl ImageData {
 		})
 	}
 
+	/// Includes the memory required for mipmaps
 	pub fn memory_required(dimensions: DimensionsU32) -> u64 {
-		Self::calculate_allocation(dimensions).map_or(0, |allocation| allocation.memory)
+		iter::successors(Some(dimensions), |&level| Self::mipmap_dimensions(level))
+			.map(|level| {
+				Self::calculate_allocation(level).map_or(0, |allocation| allocation.memory)
+			})
+			.sum()
+	}
+
+	/// Dimensions of the smallest mipmap level that is at least as large as
+	/// `minimum`
+	fn reduced_dimensions(dimensions: DimensionsU32, minimum: DimensionsU32) -> DimensionsU32 {
+		iter::successors(Some(dimensions), |&level| Self::mipmap_dimensions(level))
+			.take_while(|level| level.covers(minimum))
+			.last()
+			.unwrap_or(dimensions)
+	}
+
+	/// Returns the dimensions of the next mipmap level, if the image is large
+	/// enough to need one
+	fn mipmap_dimensions(dimensions: DimensionsU32) -> Option<DimensionsU32> {
+		let width = u32::from(dimensions.width);
+		let height = u32::from(dimensions.height);
+
+		(width.max(height) > MIPMAP_MIN_SIZE && width > 1 && height > 1)
+			.then(|| DimensionsU32::from((width.div_ceil(2), height.div_ceil(2))))
+	}
+
+	/// Blocking on CPU
+	pub fn build_mipmaps(&mut self) {
+		let mut mipmaps: Vec<ImageData> = Vec::new();
+
+		while let Some(mipmap) = mipmaps.last().unwrap_or(self).reduce() {
+			mipmaps.push(mipmap);
+		}
+
+		self.mipmaps = mipmaps;
+	}
+
+	/// Replace the image with its smallest mipmap level that is at least as
+	/// large as `minimum`, discarding the larger levels
+	fn discard_larger(&mut self, minimum: DimensionsU32) {
+		let Some(level) = self
+			.mipmaps
+			.iter()
+			.rposition(|mipmap| mipmap.covers(minimum))
+		else {
+			return;
+		};
+		let mut mipmaps = mem::take(&mut self.mipmaps);
+		let smaller = mipmaps.split_off(level + 1);
+
+		*self = mipmaps.pop().unwrap();
+		self.mipmaps = smaller;
+	}
+
+	fn covers(&self, minimum: DimensionsU32) -> bool {
+		u32::try_from(i32::from(self.width)).is_ok_and(|width| width >= u32::from(minimum.width))
+			&& u32::try_from(i32::from(self.height))
+				.is_ok_and(|height| height >= u32::from(minimum.height))
+	}
+
+	/// The image is narrower than `width`, so it would need to be enlarged
+	fn reduced_below(&self, width: f64) -> bool {
+		self.data.is_some() && f64::from(i32::from(self.width)) < width.floor()
+	}
+
+	/// Create an image half the size by averaging each 2x2 block of pixels
+	fn reduce(&self) -> Option<ImageData> {
+		let data = self.data.as_ref()?;
+		let src_width = usize::try_from(i32::from(self.width)).ok()?;
+		let src_height = usize::try_from(i32::from(self.height)).ok()?;
+		let dimensions = Self::mipmap_dimensions(DimensionsU32::from((
+			u32::try_from(src_width).ok()?,
+			u32::try_from(src_height).ok()?,
+		)))?;
+		let mut builder = ImageData::builder(dimensions).ok()?;
+		let dst_width = usize::try_from(u32::from(dimensions.width)).ok()?;
+
+		for (y, row) in builder.buffer.chunks_exact_mut(dst_width).enumerate() {
+			let row0 = &data[y * 2 * src_width..][..src_width];
+			let row1 = &data[min(y * 2 + 1, src_height - 1) * src_width..][..src_width];
+
+			for (x, pixel) in row.iter_mut().enumerate() {
+				let x0 = x * 2;
+				let x1 = min(x * 2 + 1, src_width - 1);
+
+				*pixel = average_pixels([row0[x0], row0[x1], row1[x0], row1[x1]]);
+			}
+		}
+
+		Some(builder.into())
 	}
 
 	pub fn builder(dimensions: DimensionsU32) -> Result<ImageDataBuilder, Error> {
@@ -539,12 +1266,45 @@ impl ImageData {
 			width: Xi32::from(-1),
 			height: Yi32::from(-1),
 			stride: -1,
+			mipmaps: Vec::new(),
+		}
+	}
+
+	fn pixel(&self, x: Xu32, y: Yu32) -> Option<Pixel> {
+		let width = usize::try_from(i32::from(self.width)).ok()?;
+		let x = usize::try_from(u32::from(x)).ok()?;
+		let y = usize::try_from(u32::from(y)).ok()?;
+
+		if x >= width {
+			return None;
+		}
+
+		self.data
+			.as_ref()?
+			.get(y.checked_mul(width)?.checked_add(x)?)
+			.copied()
+	}
+
+	/// Use the smallest mipmap level that is at least as large as the image
+	/// will be when drawn at `width`
+	pub fn with_surface<F: FnOnce(Option<&cairo::ImageSurface>, bool)>(
+		&mut self,
+		width: f64,
+		func: F,
+	) {
+		match self
+			.mipmaps
+			.iter()
+			.rposition(|mipmap| f64::from(i32::from(mipmap.width)) >= width)
+		{
+			Some(level) => self.mipmaps[level].with_level_surface(func),
+			None => self.with_level_surface(func),
 		}
 	}
 
 	/// Calls the given closure with a temporary Cairo image surface. After the closure has returned
 	/// there must be no further references to the surface.
-	pub fn with_surface<F: FnOnce(Option<&cairo::ImageSurface>, bool)>(&mut self, func: F) {
+	fn with_level_surface<F: FnOnce(Option<&cairo::ImageSurface>, bool)>(&mut self, func: F) {
 		// Temporarily move out the pixels
 		if let Some(image) = self.data.take() {
 			// A new return location that is then passed to our helper struct below
@@ -0,0 +1,248 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Sessions are stored in "$XDG_STATE_HOME/fiv/" with one file for each set of
+// command line filenames (usually a single directory), named using a hash of
+// the canonical paths. The file is a list of "key=value" lines and the paths
+// are repeated in it so that hash collisions can be detected.
+
+use super::util::percent;
+use super::{Orientation, Rotate};
+use anyhow::{Error, anyhow};
+use log::error;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, DirBuilder};
+use std::io::{self, Write as _};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{self, Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, strum::AsRefStr, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ZoomMode {
+	#[default]
+	Fit,
+	Actual,
+	FitWidth,
+	FitHeight,
+	Fill,
+}
+
+/// View settings that are saved with the session
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SessionView {
+	pub zoom: ZoomMode,
+	pub af_points: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Session {
+	/// Absolute path of the current image
+	pub filename: Option<PathBuf>,
+	pub view: SessionView,
+
+	/// Orientations that have been changed but not saved to the file
+	pub orientations: BTreeMap<PathBuf, Orientation>,
+}
+
+#[derive(Debug)]
+pub struct SessionFile {
+	filename: PathBuf,
+	paths: Vec<PathBuf>,
+}
+
+impl SessionFile {
+	pub fn new(paths: &[PathBuf]) -> Result<Self, Error> {
+		let paths: Vec<PathBuf> = paths
+			.iter()
+			.map(|path| {
+				path.canonicalize()
+					.or_else(|_| path::absolute(path))
+					.unwrap_or_else(|_| path.clone())
+			})
+			.collect();
+
+		// 64-bit FNV-1a
+		let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+		for byte in paths
+			.iter()
+			.flat_map(|path| path.as_os_str().as_bytes().iter().chain(&[0]))
+		{
+			hash ^= u64::from(*byte);
+			hash = hash.wrapping_mul(0x0100_0000_01b3);
+		}
+
+		Ok(Self {
+			filename: state_directory()?.join(format!("{hash:016x}")),
+			paths,
+		})
+	}
+
+	pub fn filename(&self) -> &Path {
+		&self.filename
+	}
+
+	/// Blocking on I/O
+	pub fn load(&self) -> Session {
+		match fs::read_to_string(&self.filename) {
+			Ok(text) => self.parse(&text).unwrap_or_default(),
+			Err(err) => {
+				if err.kind() != io::ErrorKind::NotFound {
+					error!("{}: {err}", self.filename.display());
+				}
+				Session::default()
+			}
+		}
+	}
+
+	/// Blocking on I/O
+	pub fn save(&self, text: &str) -> Result<(), Error> {
+		let directory = self
+			.filename
+			.parent()
+			.ok_or_else(|| anyhow!("Invalid filename: {}", self.filename.display()))?;
+		let mut temp_basename = OsString::from(".");
+
+		temp_basename.push(self.filename.file_name().unwrap_or_default());
+		temp_basename.push(format!(".{}.tmp", std::process::id()));
+
+		let temp_filename = directory.join(temp_basename);
+
+		DirBuilder::new()
+			.recursive(true)
+			.mode(0o700)
+			.create(directory)?;
+
+		fs::File::create(&temp_filename)
+			.and_then(|mut file| {
+				file.write_all(text.as_bytes())?;
+				file.sync_all()
+			})
+			.and_then(|()| fs::rename(&temp_filename, &self.filename))
+			.inspect_err(|_| {
+				let _ = fs::remove_file(&temp_filename);
+			})?;
+		Ok(())
+	}
+
+	pub fn serialise(&self, session: &Session) -> String {
+		let mut text = String::new();
+
+		for path in &self.paths {
+			writeln!(text, "path={}", percent::encode_path(path)).unwrap();
+		}
+
+		if let Some(filename) = &session.filename {
+			writeln!(text, "filename={}", percent::encode_path(filename)).unwrap();
+		}
+
+		writeln!(text, "zoom={}", session.view.zoom.as_ref()).unwrap();
+		writeln!(text, "af-points={}", session.view.af_points).unwrap();
+
+		for (filename, orientation) in &session.orientations {
+			writeln!(
+				text,
+				"orientation={},{},{}",
+				degrees(orientation.rotate),
+				u8::from(orientation.horizontal_flip),
+				percent::encode_path(filename)
+			)
+			.unwrap();
+		}
+
+		text
+	}
+
+	/// Returns `None` if the session is for different paths
+	fn parse(&self, text: &str) -> Option<Session> {
+		let mut session = Session::default();
+		let mut paths = Vec::new();
+
+		// Invalid lines are ignored
+		for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+			match key {
+				"path" => paths.extend(percent::decode_path(value)),
+				"filename" => session.filename = percent::decode_path(value),
+				"zoom" => {
+					if let Ok(zoom) = ZoomMode::from_str(value) {
+						session.view.zoom = zoom;
+					}
+				}
+				"af-points" => {
+					if let Ok(af_points) = value.parse() {
+						session.view.af_points = af_points;
+					}
+				}
+				"orientation" => {
+					if let Some((filename, orientation)) = parse_orientation(value) {
+						session.orientations.insert(filename, orientation);
+					}
+				}
+				_ => (),
+			}
+		}
+
+		(paths == self.paths).then_some(session)
+	}
+}
+
+fn state_directory() -> Result<PathBuf, Error> {
+	let state_home = match std::env::var_os("XDG_STATE_HOME") {
+		Some(directory) if Path::new(&directory).is_absolute() => PathBuf::from(directory),
+		_ => {
+			let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+			[home.as_ref(), Path::new(".local/state")].iter().collect()
+		}
+	};
+
+	Ok(state_home.join("fiv"))
+}
+
+fn degrees(rotate: Rotate) -> u16 {
+	match rotate {
+		Rotate::Rotate0 => 0,
+		Rotate::Rotate90 => 90,
+		Rotate::Rotate180 => 180,
+		Rotate::Rotate270 => 270,
+	}
+}
+
+fn parse_orientation(value: &str) -> Option<(PathBuf, Orientation)> {
+	let mut fields = value.splitn(3, ',');
+	let rotate = match fields.next()? {
+		"0" => Rotate::Rotate0,
+		"90" => Rotate::Rotate90,
+		"180" => Rotate::Rotate180,
+		"270" => Rotate::Rotate270,
+		_ => return None,
+	};
+	let horizontal_flip = match fields.next()? {
+		"0" => false,
+		"1" => true,
+		_ => return None,
+	};
+
+	Some((
+		percent::decode_path(fields.next()?)?,
+		Orientation::new(rotate, horizontal_flip),
+	))
+}
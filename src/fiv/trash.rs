@@ -0,0 +1,167 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Implementation of the home trash directory from the FreeDesktop.org Trash
+// specification: https://specifications.freedesktop.org/trash-spec/latest/
+//
+// Files on other filesystems are not supported because that requires the use
+// of a trash directory at the top of that filesystem.
+
+use super::util::percent;
+use anyhow::{Error, anyhow, bail};
+use gtk::glib;
+use log::error;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{self, Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct Trashed {
+	/// Absolute path of the file before it was moved to the trash
+	filename: PathBuf,
+	file: PathBuf,
+	info: PathBuf,
+}
+
+/// Move a file to the trash
+///
+/// Blocking on I/O
+pub fn trash(filename: &Path) -> Result<Trashed, Error> {
+	let trash_directory = home_trash_directory()?;
+	let files_directory = trash_directory.join("files");
+	let info_directory = trash_directory.join("info");
+	let basename = filename
+		.file_name()
+		.ok_or_else(|| anyhow!("Invalid filename: {}", filename.display()))?;
+	let filename = path::absolute(filename)?;
+
+	for directory in [&files_directory, &info_directory] {
+		DirBuilder::new()
+			.recursive(true)
+			.mode(0o700)
+			.create(directory)?;
+	}
+
+	for count in 1_u32.. {
+		let mut name = OsString::from(basename);
+
+		if count > 1 {
+			name.push(format!(".{count}"));
+		}
+
+		let mut info_name = name.clone();
+		info_name.push(".trashinfo");
+
+		let trashed = Trashed {
+			filename: filename.clone(),
+			file: files_directory.join(&name),
+			info: info_directory.join(info_name),
+		};
+
+		if fs::symlink_metadata(&trashed.file).is_ok() {
+			continue;
+		}
+
+		match trashed.write_info() {
+			Ok(()) => {
+				trashed.rename_to_trash()?;
+				return Ok(trashed);
+			}
+
+			Err(err) => {
+				if err.kind() != io::ErrorKind::AlreadyExists {
+					return Err(err.into());
+				}
+			}
+		}
+	}
+
+	bail!("No space in trash for {}", filename.display())
+}
+
+fn home_trash_directory() -> Result<PathBuf, Error> {
+	let data_home = match env::var_os("XDG_DATA_HOME") {
+		Some(directory) if Path::new(&directory).is_absolute() => PathBuf::from(directory),
+		_ => {
+			let home = env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+			[home.as_ref(), Path::new(".local/share")].iter().collect()
+		}
+	};
+
+	Ok(data_home.join("Trash"))
+}
+
+impl Trashed {
+	/// Move the file to the trash again, after it has been restored
+	///
+	/// Blocking on I/O
+	pub fn trash(&self) -> Result<(), Error> {
+		self.write_info()?;
+		self.rename_to_trash()
+	}
+
+	/// Move the file out of the trash back to its original location
+	///
+	/// Blocking on I/O
+	pub fn restore(&self) -> Result<(), Error> {
+		if fs::symlink_metadata(&self.filename).is_ok() {
+			bail!("File already exists");
+		}
+
+		fs::rename(&self.file, &self.filename)?;
+		fs::remove_file(&self.info).unwrap_or_else(|err| error!("{}: {err}", self.info.display()));
+		Ok(())
+	}
+
+	fn write_info(&self) -> io::Result<()> {
+		let deletion_date = glib::DateTime::now_local()
+			.and_then(|now| now.format("%Y-%m-%dT%H:%M:%S"))
+			.map_err(io::Error::other)?;
+		let mut file = OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&self.info)?;
+
+		write!(
+			file,
+			"[Trash Info]\nPath={}\nDeletionDate={deletion_date}\n",
+			percent::encode_path(&self.filename)
+		)
+		.and_then(|()| file.sync_all())
+		.inspect_err(|_| self.remove_info())
+	}
+
+	fn rename_to_trash(&self) -> Result<(), Error> {
+		fs::rename(&self.filename, &self.file).map_err(|err| {
+			self.remove_info();
+
+			if err.kind() == io::ErrorKind::CrossesDevices {
+				anyhow!("Unable to move files on other filesystems to the trash")
+			} else {
+				err.into()
+			}
+		})
+	}
+
+	fn remove_info(&self) {
+		fs::remove_file(&self.info).unwrap_or_else(|err| error!("{}: {err}", self.info.display()));
+	}
+}
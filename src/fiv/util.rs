@@ -17,7 +17,10 @@
  */
 
 pub mod exiv2_byte_order;
+pub mod mapped_file;
+pub mod memory;
 pub mod numeric;
+pub mod percent;
 
 use std::sync::{Condvar, Mutex};
 
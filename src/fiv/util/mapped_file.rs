@@ -0,0 +1,279 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Files are mapped into memory to read them, but if another process
+// truncates a file while it's mapped then reading the part that no longer
+// exists raises SIGBUS. The signal handler replaces those pages with zeros
+// so that reading can continue, and the file is reported as changed.
+
+use anyhow::Error;
+use memmap2::{Advice, Mmap, UncheckedAdvice};
+use std::ffi::{c_int, c_void};
+use std::fs::{File, Metadata};
+use std::io::Read;
+use std::mem;
+use std::ops::Deref;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{LazyLock, OnceLock};
+use std::time::SystemTime;
+
+/// Maximum number of files that can be mapped at the same time, which is
+/// more than the number of threads that read files
+const SLOTS: usize = 256;
+
+const UNUSED: usize = 0;
+const RESERVED: usize = usize::MAX;
+
+static STARTS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(UNUSED) }; SLOTS];
+static LENGTHS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+static TRUNCATED: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Files are read into a buffer instead of being mapped if the signal
+/// handler can't be installed
+static HANDLER: LazyLock<bool> = LazyLock::new(install_handler);
+
+/// Handler that was installed before this one (e.g. for detecting stack
+/// overflows), which faults outside of the mapped files are passed to
+static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+
+/// Identifies the version of a file that was opened, to detect it being
+/// replaced or modified
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FileIdentity {
+	device: u64,
+	inode: u64,
+	size: u64,
+	modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+pub struct MappedFile {
+	file: File,
+	contents: Contents,
+	identity: FileIdentity,
+}
+
+#[derive(Debug)]
+enum Contents {
+	Mapped { map: Mmap, slot: usize },
+	Read(Vec<u8>),
+}
+
+impl From<&Metadata> for FileIdentity {
+	fn from(metadata: &Metadata) -> Self {
+		Self {
+			device: metadata.dev(),
+			inode: metadata.ino(),
+			size: metadata.len(),
+			modified: metadata.modified().ok(),
+		}
+	}
+}
+
+impl MappedFile {
+	/// Blocking on I/O
+	pub fn open(filename: &Path) -> Result<Self, Error> {
+		let mut file = File::open(filename)?;
+		let identity = FileIdentity::from(&file.metadata()?);
+
+		if *HANDLER && identity.size > 0 {
+			let map = unsafe { Mmap::map(&file)? };
+
+			map.advise(Advice::DontDump)?;
+			if let Some(slot) = register(&map) {
+				return Ok(Self {
+					file,
+					contents: Contents::Mapped { map, slot },
+					identity,
+				});
+			}
+		}
+
+		// Reading into a buffer can't be affected by the file being truncated
+		let mut buffer = Vec::new();
+
+		file.read_to_end(&mut buffer)?;
+		Ok(Self {
+			file,
+			contents: Contents::Read(buffer),
+			identity,
+		})
+	}
+
+	/// The version of the file when it was opened
+	pub fn identity(&self) -> FileIdentity {
+		self.identity
+	}
+
+	/// Returns true if the file has been truncated or modified since it was
+	/// opened, so the contents that have been read may be incomplete
+	///
+	/// Blocking on I/O
+	pub fn changed(&self) -> bool {
+		if let Contents::Mapped { slot, .. } = self.contents
+			&& TRUNCATED[slot].load(Ordering::Acquire)
+		{
+			return true;
+		}
+
+		self.file.metadata().map_or(true, |metadata| {
+			FileIdentity::from(&metadata) != self.identity
+		})
+	}
+
+	/// The contents are about to be read
+	pub fn will_need(&self) {
+		if let Contents::Mapped { map, .. } = &self.contents {
+			let _ = map.advise(Advice::WillNeed);
+		}
+	}
+
+	/// The contents are no longer needed in memory
+	pub fn dont_need(&self) {
+		if let Contents::Mapped { map, .. } = &self.contents {
+			let _ = unsafe { map.unchecked_advise(UncheckedAdvice::DontNeed) };
+		}
+	}
+}
+
+impl Deref for MappedFile {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match &self.contents {
+			Contents::Mapped { map, .. } => map,
+			Contents::Read(buffer) => buffer,
+		}
+	}
+}
+
+impl Drop for MappedFile {
+	fn drop(&mut self) {
+		// The mapping is removed after this
+		if let Contents::Mapped { slot, .. } = self.contents {
+			STARTS[slot].store(UNUSED, Ordering::Release);
+		}
+	}
+}
+
+/// Returns the slot that the mapping has been registered in, or `None` if
+/// there are no free slots
+fn register(map: &Mmap) -> Option<usize> {
+	let slot = STARTS.iter().position(|start| {
+		start
+			.compare_exchange(UNUSED, RESERVED, Ordering::AcqRel, Ordering::Relaxed)
+			.is_ok()
+	})?;
+
+	LENGTHS[slot].store(map.len(), Ordering::Release);
+	TRUNCATED[slot].store(false, Ordering::Release);
+	STARTS[slot].store(map.as_ptr().addr(), Ordering::Release);
+	Some(slot)
+}
+
+fn install_handler() -> bool {
+	let Ok(page_size) = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }) else {
+		return false;
+	};
+
+	PAGE_SIZE.store(page_size, Ordering::Release);
+
+	unsafe {
+		let mut previous: libc::sigaction = mem::zeroed();
+
+		if libc::sigaction(libc::SIGBUS, ptr::null(), &raw mut previous) != 0
+			|| PREVIOUS.set(previous).is_err()
+		{
+			return false;
+		}
+
+		let mut action: libc::sigaction = mem::zeroed();
+
+		action.sa_sigaction = (sigbus as *const ()).addr();
+		action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+		libc::sigemptyset(&raw mut action.sa_mask);
+		libc::sigaction(libc::SIGBUS, &raw const action, ptr::null_mut()) == 0
+	}
+}
+
+/// Only async-signal-safe operations can be used here
+extern "C" fn sigbus(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+	let address = unsafe { (*info).si_addr() };
+	let page_size = PAGE_SIZE.load(Ordering::Acquire);
+
+	for slot in 0..SLOTS {
+		let start = STARTS[slot].load(Ordering::Acquire);
+
+		if start == UNUSED || start == RESERVED {
+			continue;
+		}
+
+		if address.addr() >= start && address.addr() - start < LENGTHS[slot].load(Ordering::Acquire)
+		{
+			let page = address.map_addr(|address| address & !(page_size - 1));
+			let result = unsafe {
+				libc::mmap(
+					page,
+					page_size,
+					libc::PROT_READ,
+					libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+					-1,
+					0,
+				)
+			};
+
+			if result != libc::MAP_FAILED {
+				TRUNCATED[slot].store(true, Ordering::Release);
+				return;
+			}
+		}
+	}
+
+	chain(signal, info, context);
+}
+
+/// Pass the signal to the previous handler, or let the fault happen again
+/// with the default action
+fn chain(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+	let Some(previous) = PREVIOUS.get() else {
+		unsafe { libc::signal(signal, libc::SIG_DFL) };
+		return;
+	};
+
+	match previous.sa_sigaction {
+		// Returning from an ignored fault would repeat it forever
+		libc::SIG_DFL | libc::SIG_IGN => unsafe {
+			libc::signal(signal, libc::SIG_DFL);
+		},
+		handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+			let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+				unsafe { mem::transmute(handler) };
+
+			handler(signal, info, context);
+		}
+		handler => {
+			let handler: extern "C" fn(c_int) = unsafe { mem::transmute(handler) };
+
+			handler(signal);
+		}
+	}
+}
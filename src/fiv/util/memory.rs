@@ -0,0 +1,121 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// System memory information from "/proc/meminfo", cgroup v2 limits and
+// pressure stall information for the cgroup or the whole system. Everything
+// is optional because it depends on the kernel version and configuration.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Memory stalls above this percentage of the last 10 seconds mean that the
+/// system is under pressure
+const PRESSURE_THRESHOLD: f64 = 10.0;
+
+/// Less available memory than this means that the system is under pressure
+const LOW_AVAILABLE: u64 = 512 * 1024 * 1024;
+
+/// Memory available to this process without swapping, limited by the cgroup
+/// that it's in
+///
+/// Blocking on I/O
+pub fn available() -> Option<u64> {
+	match (system_available(), cgroup_available()) {
+		(Some(system), Some(cgroup)) => Some(system.min(cgroup)),
+		(system, cgroup) => system.or(cgroup),
+	}
+}
+
+/// The system is short of memory, either because tasks are stalled waiting
+/// for memory or because very little is available
+///
+/// Blocking on I/O
+pub fn under_pressure() -> bool {
+	pressure().is_some_and(|avg10| avg10 >= PRESSURE_THRESHOLD)
+		|| available().is_some_and(|available| available < LOW_AVAILABLE)
+}
+
+fn system_available() -> Option<u64> {
+	let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+
+	meminfo.lines().find_map(|line| {
+		let kilobytes = line
+			.strip_prefix("MemAvailable:")?
+			.trim()
+			.strip_suffix(" kB")?;
+
+		kilobytes.parse::<u64>().ok()?.checked_mul(1024)
+	})
+}
+
+/// Directory of the cgroup that this process is in
+fn cgroup() -> Option<PathBuf> {
+	let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+	let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+
+	Some(Path::new("/sys/fs/cgroup").join(path.trim_start_matches('/')))
+}
+
+/// Lowest amount of memory remaining before reaching the limit of the cgroup
+/// or any of its parents
+///
+/// The memory used by a cgroup includes the page cache for files that it has
+/// read, which includes the images. Inactive file pages can be reclaimed
+/// instead of running out of memory, so they're counted as available.
+fn cgroup_available() -> Option<u64> {
+	cgroup()?
+		.ancestors()
+		.take_while(|ancestor| ancestor.starts_with("/sys/fs/cgroup"))
+		.filter_map(|ancestor| {
+			let max = read_u64(&ancestor.join("memory.max"))?;
+			let current = read_u64(&ancestor.join("memory.current")).unwrap_or(0);
+			let inactive_file = read_stat(&ancestor.join("memory.stat"), "inactive_file");
+
+			Some(max.saturating_sub(current.saturating_sub(inactive_file.unwrap_or(0))))
+		})
+		.min()
+}
+
+/// Percentage of the last 10 seconds that some tasks were stalled waiting for
+/// memory, in the cgroup that this process is in or the whole system
+fn pressure() -> Option<f64> {
+	let pressure = cgroup()
+		.and_then(|directory| fs::read_to_string(directory.join("memory.pressure")).ok())
+		.or_else(|| fs::read_to_string("/proc/pressure/memory").ok())?;
+
+	pressure
+		.lines()
+		.find_map(|line| line.strip_prefix("some "))?
+		.split_whitespace()
+		.find_map(|field| field.strip_prefix("avg10="))?
+		.parse()
+		.ok()
+}
+
+/// Returns `None` if the value is "max" (unlimited) or can't be read
+fn read_u64(filename: &Path) -> Option<u64> {
+	fs::read_to_string(filename).ok()?.trim().parse().ok()
+}
+
+/// Value of a key in a file with a key and value on each line
+fn read_stat(filename: &Path, key: &str) -> Option<u64> {
+	fs::read_to_string(filename)
+		.ok()?
+		.lines()
+		.find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.parse().ok())
+}
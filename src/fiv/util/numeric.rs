@@ -437,6 +437,11 @@ impl DimensionsU32 {
 	pub fn rotate90(self) -> Self {
 		Self::new(u32::from(self.height).into(), u32::from(self.width).into())
 	}
+
+	/// Both dimensions are at least as large as `other`
+	pub fn covers(self, other: Self) -> bool {
+		self.width >= other.width && self.height >= other.height
+	}
 }
 
 impl From<(u32, u32)> for DimensionsU32 {
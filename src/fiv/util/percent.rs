@@ -0,0 +1,54 @@
+/*
+ * fiv - Fast Image Viewer
+ * Copyright 2025  Simon Arlott
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Percent-encode a path in the same way as a URL path
+pub fn encode_path(path: &Path) -> String {
+	let mut encoded = String::new();
+
+	for &byte in path.as_os_str().as_bytes() {
+		if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+			encoded.push(char::from(byte));
+		} else {
+			write!(encoded, "%{byte:02X}").unwrap();
+		}
+	}
+
+	encoded
+}
+
+pub fn decode_path(encoded: &str) -> Option<PathBuf> {
+	let mut bytes = encoded.bytes();
+	let mut decoded = Vec::with_capacity(encoded.len());
+
+	while let Some(byte) = bytes.next() {
+		if byte == b'%' {
+			let hex = [bytes.next()?, bytes.next()?];
+
+			decoded.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+		} else {
+			decoded.push(byte);
+		}
+	}
+
+	Some(OsString::from_vec(decoded).into())
+}
@@ -17,15 +17,18 @@
  */
 
 use super::Files;
-use super::draw::DrawingArea;
-use crate::fiv::{Mark, Navigate, Rotate};
+use super::draw::{DrawingArea, Fit};
+use crate::fiv::numeric::{DimensionsU32, Sf64};
+use crate::fiv::{Cancel, Current, Filter, Mark, Navigate, Rotate, SessionView};
 use gtk::gdk_pixbuf::{Colorspace, Pixbuf};
 use gtk::gio::{Menu, SimpleAction};
 use gtk::glib::Variant;
 use gtk::glib::once_cell::unsync::OnceCell;
 use gtk::{gdk, gio, glib, prelude::*, subclass::prelude::*};
+use std::fmt::Write as _;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, atomic};
+use std::time::Duration;
 
 #[derive(Debug, Default)]
 pub struct Application {
@@ -35,12 +38,40 @@ pub struct Application {
 	state: Mutex<State>,
 	drawing_area: OnceCell<Rc<DrawingArea>>,
 	view_full_screen_action: OnceCell<SimpleAction>,
+	view_af_points_action: OnceCell<SimpleAction>,
+	view_freeze_pixel_action: OnceCell<SimpleAction>,
+	view_loupe_100_action: OnceCell<SimpleAction>,
+	view_loupe_200_action: OnceCell<SimpleAction>,
+	view_slideshow_action: OnceCell<SimpleAction>,
+	view_pause_slideshow_action: OnceCell<SimpleAction>,
+	view_marked_action: OnceCell<SimpleAction>,
+	view_unmarked_action: OnceCell<SimpleAction>,
 }
 
 #[derive(Debug, Default)]
 struct State {
 	full_screen: bool,
 	af_points: bool,
+	slideshow: Option<Slideshow>,
+	slideshow_options: SlideshowOptions,
+}
+
+#[derive(Debug, Default)]
+struct SlideshowOptions {
+	repeat: bool,
+	shuffle: bool,
+}
+
+#[derive(Debug)]
+struct Slideshow {
+	paused: bool,
+
+	/// Timer to move to the next image, only started after the current image
+	/// has been loaded
+	timer: Option<glib::SourceId>,
+
+	/// Full screen was enabled by the slideshow
+	leave_full_screen: bool,
 }
 
 #[glib::object_subclass]
@@ -65,17 +96,55 @@ enum WinAction {
 	ImageRotateRight,
 	ImageFlipHorizontal,
 	ImageFlipVertical,
+	ImageSaveOrientation,
+	ImageTrash,
+	ImageFailedFiles,
+	EditUndo,
+	EditRedo,
 	EditMark,
 	EditToggleMark,
 	EditUnmark,
+	EditCopyPixel,
 	ViewFirst,
 	ViewPrevious,
 	ViewNext,
 	ViewLast,
+	ViewBack10,
+	ViewForward10,
+	ViewBack100,
+	ViewForward100,
+	ViewRandom,
+	ViewGoTo,
+	ViewCompareOff,
+	ViewCompare2,
+	ViewCompare3,
+	ViewCompare4,
+	ViewPreviousPane,
+	ViewNextPane,
+	ViewZoomIn,
+	ViewZoomOut,
+	ViewZoom25,
+	ViewZoom50,
+	ViewZoom200,
+	ViewZoom400,
 	ViewZoomActual,
 	ViewZoomFit,
+	ViewZoomFitWidth,
+	ViewZoomFitHeight,
+	ViewZoomFill,
+	ViewLockZoom,
+	ViewSlideshow,
+	ViewPauseSlideshow,
+	ViewSlideshowLoop,
+	ViewSlideshowShuffle,
 	ViewFullScreen,
 	ViewAFPoints,
+	ViewPixelInspector,
+	ViewFreezePixel,
+	ViewLoupe100,
+	ViewLoupe200,
+	ViewMarked,
+	ViewUnmarked,
 }
 
 trait MenuExtActionEnum<T> {
@@ -228,13 +297,10 @@ impl Application {
 	fn build_menu_bar(&self) {
 		let obj = self.obj();
 		let app = obj.dynamic_cast_ref::<gtk::Application>().unwrap();
-		let files = self.files.get().unwrap();
 		let menu_bar = Menu::new();
 
 		menu_bar.append_submenu(Some("_Image"), &self.build_image_menu());
-		if files.mark_supported() {
-			menu_bar.append_submenu(Some("_Edit"), &self.build_edit_menu());
-		}
+		menu_bar.append_submenu(Some("_Edit"), &self.build_edit_menu());
 		menu_bar.append_submenu(Some("_View"), &self.build_view_menu());
 
 		app.set_menubar(Some(&menu_bar));
@@ -244,6 +310,7 @@ impl Application {
 		let menu = Menu::new();
 		let rotate_section = Menu::new();
 		let flip_section = Menu::new();
+		let file_section = Menu::new();
 		let app_section = Menu::new();
 
 		rotate_section.append_ext("Rotate _Left", WinAction::ImageRotateLeft);
@@ -258,6 +325,18 @@ impl Application {
 		self.add_action(WinAction::ImageFlipVertical, Self::files_action, &["v"]);
 		menu.append_section(None, &flip_section);
 
+		file_section.append_ext("_Save Orientation", WinAction::ImageSaveOrientation);
+		self.add_action(
+			WinAction::ImageSaveOrientation,
+			Self::files_action,
+			&["<Primary>s"],
+		);
+		file_section.append_ext("Move to _Trash", WinAction::ImageTrash);
+		self.add_action(WinAction::ImageTrash, Self::trash, &["<Primary>Delete"]);
+		file_section.append_ext("Show _Failed Files", WinAction::ImageFailedFiles);
+		self.add_action(WinAction::ImageFailedFiles, Self::failed_files, &[]);
+		menu.append_section(None, &file_section);
+
 		app_section.append_ext("_Quit", AppAction::Quit);
 		self.add_action(AppAction::Quit, Self::quit, &["<Primary>q", "q", "<Alt>F4"]);
 		menu.append_section(None, &app_section);
@@ -266,9 +345,30 @@ impl Application {
 	}
 
 	fn build_edit_menu(&self) -> Menu {
+		let files = self.files.get().unwrap();
 		let menu = Menu::new();
+		let history_section = Menu::new();
+		let copy_section = Menu::new();
 		let mark_section = Menu::new();
 
+		history_section.append_ext("_Undo", WinAction::EditUndo);
+		self.add_action(WinAction::EditUndo, Self::files_action, &["<Primary>z"]);
+		history_section.append_ext("_Redo", WinAction::EditRedo);
+		self.add_action(
+			WinAction::EditRedo,
+			Self::files_action,
+			&["<Primary><Shift>z", "<Primary>y"],
+		);
+		menu.append_section(None, &history_section);
+
+		copy_section.append_ext("_Copy Pixel Value", WinAction::EditCopyPixel);
+		self.add_action(WinAction::EditCopyPixel, Self::copy_pixel, &["<Primary>c"]);
+		menu.append_section(None, &copy_section);
+
+		if !files.mark_supported() {
+			return menu;
+		}
+
 		mark_section.append_ext("_Mark", WinAction::EditMark);
 		self.add_action(WinAction::EditMark, Self::files_action, &["Insert"]);
 		mark_section.append_ext("_Toggle mark", WinAction::EditToggleMark);
@@ -281,11 +381,12 @@ impl Application {
 	}
 
 	fn build_view_menu(&self) -> Menu {
+		let files = self.files.get().unwrap();
 		let menu = Menu::new();
-		let zoom_section = Menu::new();
 		let nav_section = Menu::new();
 		let win_section = Menu::new();
 		let overlay_section = Menu::new();
+		let filter_section = Menu::new();
 
 		nav_section.append_ext("_Previous", WinAction::ViewPrevious);
 		self.add_action(WinAction::ViewPrevious, Self::files_action, &["Left"]);
@@ -300,12 +401,11 @@ impl Application {
 		nav_section.append_ext("_Last", WinAction::ViewLast);
 		self.add_action(WinAction::ViewLast, Self::files_action, &["End"]);
 		menu.append_section(None, &nav_section);
+		menu.append_section(None, &self.build_jump_section());
 
-		zoom_section.append_ext("Norm_al Size", WinAction::ViewZoomActual);
-		self.add_action(WinAction::ViewZoomActual, Self::zoom_action, &["a", "1"]);
-		zoom_section.append_ext("Best _Fit", WinAction::ViewZoomFit);
-		self.add_action(WinAction::ViewZoomFit, Self::zoom_action, &["f"]);
-		menu.append_section(None, &zoom_section);
+		menu.append_section(None, &self.build_compare_section());
+		menu.append_section(None, &self.build_zoom_section());
+		menu.append_section(None, &self.build_slideshow_section());
 
 		win_section.append_ext("F_ull Screen", WinAction::ViewFullScreen);
 		self.view_full_screen_action
@@ -319,20 +419,232 @@ impl Application {
 		menu.append_section(None, &win_section);
 
 		overlay_section.append_ext("AF P_oints", WinAction::ViewAFPoints);
-		self.add_stateful_action(WinAction::ViewAFPoints, Self::view_af_points, &["p"], false);
+		self.view_af_points_action
+			.set(self.add_stateful_action(
+				WinAction::ViewAFPoints,
+				Self::view_af_points,
+				&["p"],
+				false,
+			))
+			.unwrap();
+		overlay_section.append_ext("Pixel _Inspector", WinAction::ViewPixelInspector);
+		self.add_stateful_action(
+			WinAction::ViewPixelInspector,
+			Self::view_pixel_inspector,
+			&["i"],
+			false,
+		);
+		overlay_section.append_ext("Free_ze Pixel Inspector", WinAction::ViewFreezePixel);
+		self.view_freeze_pixel_action
+			.set(self.add_stateful_action(
+				WinAction::ViewFreezePixel,
+				Self::view_freeze_pixel,
+				&["<Shift>i"],
+				false,
+			))
+			.unwrap();
+		overlay_section.append_ext("Loupe _100%", WinAction::ViewLoupe100);
+		self.view_loupe_100_action
+			.set(self.add_stateful_action(
+				WinAction::ViewLoupe100,
+				Self::view_loupe_100,
+				&["o"],
+				false,
+			))
+			.unwrap();
+		overlay_section.append_ext("Loupe _200%", WinAction::ViewLoupe200);
+		self.view_loupe_200_action
+			.set(self.add_stateful_action(
+				WinAction::ViewLoupe200,
+				Self::view_loupe_200,
+				&["<Shift>o"],
+				false,
+			))
+			.unwrap();
 		menu.append_section(None, &overlay_section);
 
+		if files.mark_supported() {
+			filter_section.append_ext("_Marked Only", WinAction::ViewMarked);
+			self.view_marked_action
+				.set(self.add_stateful_action(
+					WinAction::ViewMarked,
+					Self::view_marked,
+					&["m"],
+					false,
+				))
+				.unwrap();
+			filter_section.append_ext("Unmar_ked Only", WinAction::ViewUnmarked);
+			self.view_unmarked_action
+				.set(self.add_stateful_action(
+					WinAction::ViewUnmarked,
+					Self::view_unmarked,
+					&["u"],
+					false,
+				))
+				.unwrap();
+			menu.append_section(None, &filter_section);
+		}
+
 		menu
 	}
 
+	fn build_jump_section(&self) -> Menu {
+		let jump_section = Menu::new();
+
+		jump_section.append_ext("_Back 10", WinAction::ViewBack10);
+		self.add_action(WinAction::ViewBack10, Self::files_action, &["Page_Up"]);
+		jump_section.append_ext("Forward 1_0", WinAction::ViewForward10);
+		self.add_action(WinAction::ViewForward10, Self::files_action, &["Page_Down"]);
+		jump_section.append_ext("Back 100", WinAction::ViewBack100);
+		self.add_action(
+			WinAction::ViewBack100,
+			Self::files_action,
+			&["<Shift>Page_Up"],
+		);
+		jump_section.append_ext("Forward 100", WinAction::ViewForward100);
+		self.add_action(
+			WinAction::ViewForward100,
+			Self::files_action,
+			&["<Shift>Page_Down"],
+		);
+		jump_section.append_ext("_Random", WinAction::ViewRandom);
+		self.add_action(WinAction::ViewRandom, Self::files_action, &["x"]);
+		jump_section.append_ext("_Go To…", WinAction::ViewGoTo);
+		self.add_action(WinAction::ViewGoTo, Self::go_to, &["g", "<Primary>g"]);
+		jump_section
+	}
+
+	fn build_compare_section(&self) -> Menu {
+		let compare_section = Menu::new();
+
+		compare_section.append_ext("_Single Image", WinAction::ViewCompareOff);
+		self.add_action(WinAction::ViewCompareOff, Self::files_action, &["0"]);
+		compare_section.append_ext("Compare _2 Images", WinAction::ViewCompare2);
+		self.add_action(WinAction::ViewCompare2, Self::files_action, &["2"]);
+		compare_section.append_ext("Compare _3 Images", WinAction::ViewCompare3);
+		self.add_action(WinAction::ViewCompare3, Self::files_action, &["3"]);
+		compare_section.append_ext("Compare _4 Images", WinAction::ViewCompare4);
+		self.add_action(WinAction::ViewCompare4, Self::files_action, &["4"]);
+		compare_section.append_ext("Previous Pan_e", WinAction::ViewPreviousPane);
+		self.add_action(
+			WinAction::ViewPreviousPane,
+			Self::files_action,
+			&["bracketleft"],
+		);
+		compare_section.append_ext("Next Pa_ne", WinAction::ViewNextPane);
+		self.add_action(
+			WinAction::ViewNextPane,
+			Self::files_action,
+			&["bracketright"],
+		);
+		compare_section
+	}
+
+	fn build_zoom_section(&self) -> Menu {
+		let zoom_section = Menu::new();
+		let zoom_levels = Menu::new();
+
+		zoom_section.append_ext("Zoom _In", WinAction::ViewZoomIn);
+		self.add_action(
+			WinAction::ViewZoomIn,
+			Self::zoom_action,
+			&["plus", "equal", "KP_Add"],
+		);
+		zoom_section.append_ext("Zoom _Out", WinAction::ViewZoomOut);
+		self.add_action(
+			WinAction::ViewZoomOut,
+			Self::zoom_action,
+			&["minus", "KP_Subtract"],
+		);
+		zoom_section.append_ext("Norm_al Size", WinAction::ViewZoomActual);
+		self.add_action(WinAction::ViewZoomActual, Self::zoom_action, &["a", "1"]);
+		zoom_section.append_ext("Best _Fit", WinAction::ViewZoomFit);
+		self.add_action(WinAction::ViewZoomFit, Self::zoom_action, &["f"]);
+		zoom_section.append_ext("Fit _Width", WinAction::ViewZoomFitWidth);
+		self.add_action(WinAction::ViewZoomFitWidth, Self::zoom_action, &["w"]);
+		zoom_section.append_ext("Fit _Height", WinAction::ViewZoomFitHeight);
+		self.add_action(WinAction::ViewZoomFitHeight, Self::zoom_action, &["t"]);
+		zoom_section.append_ext("F_ill", WinAction::ViewZoomFill);
+		self.add_action(WinAction::ViewZoomFill, Self::zoom_action, &["<Shift>f"]);
+
+		zoom_levels.append_ext("25%", WinAction::ViewZoom25);
+		self.add_action(WinAction::ViewZoom25, Self::zoom_action, &[]);
+		zoom_levels.append_ext("50%", WinAction::ViewZoom50);
+		self.add_action(WinAction::ViewZoom50, Self::zoom_action, &[]);
+		zoom_levels.append_ext("100%", WinAction::ViewZoomActual);
+		zoom_levels.append_ext("200%", WinAction::ViewZoom200);
+		self.add_action(WinAction::ViewZoom200, Self::zoom_action, &[]);
+		zoom_levels.append_ext("400%", WinAction::ViewZoom400);
+		self.add_action(WinAction::ViewZoom400, Self::zoom_action, &[]);
+		zoom_section.append_submenu(Some("Zoom _Level"), &zoom_levels);
+		zoom_section.append_ext("Loc_k Zoom", WinAction::ViewLockZoom);
+		self.add_stateful_action(WinAction::ViewLockZoom, Self::view_lock_zoom, &["k"], false);
+		zoom_section
+	}
+
+	fn build_slideshow_section(&self) -> Menu {
+		let slideshow_section = Menu::new();
+
+		slideshow_section.append_ext("_Slideshow", WinAction::ViewSlideshow);
+		self.view_slideshow_action
+			.set(self.add_stateful_action(
+				WinAction::ViewSlideshow,
+				Self::view_slideshow,
+				&["F5"],
+				false,
+			))
+			.unwrap();
+		slideshow_section.append_ext("_Pause Slideshow", WinAction::ViewPauseSlideshow);
+		self.view_pause_slideshow_action
+			.set(self.add_stateful_action(
+				WinAction::ViewPauseSlideshow,
+				Self::view_pause_slideshow,
+				&["space"],
+				false,
+			))
+			.unwrap();
+		slideshow_section.append_ext("Loop Sli_deshow", WinAction::ViewSlideshowLoop);
+		self.add_stateful_action(
+			WinAction::ViewSlideshowLoop,
+			Self::view_slideshow_loop,
+			&[],
+			false,
+		);
+		slideshow_section.append_ext("S_huffle Slideshow", WinAction::ViewSlideshowShuffle);
+		self.add_stateful_action(
+			WinAction::ViewSlideshowShuffle,
+			Self::view_slideshow_shuffle,
+			&[],
+			false,
+		);
+		slideshow_section
+	}
+
 	pub fn refresh(&self) {
-		let window = self.window.get().unwrap();
 		let drawing_area = self.drawing_area.get().unwrap();
 		let files = self.files.get().unwrap();
 		let current = files.current();
 
+		self.update_title(&current);
+		drawing_area.refresh(&current.panes, current.active_pane);
+
+		if current.image.is_some()
+			&& let Some(zoom) = files.take_restored_zoom()
+		{
+			drawing_area.restore_zoom(zoom);
+		}
+
+		self.save_view();
+		self.slideshow_timer();
+	}
+
+	fn update_title(&self, current: &Current) {
+		let window = self.window.get().unwrap();
+		let drawing_area = self.drawing_area.get().unwrap();
+		let files = self.files.get().unwrap();
+
 		window.set_title(&format!(
-			"{}: {}{} ({}/{}{})",
+			"{}: {}{} ({}/{}{}{}){}{}",
 			self.app_name.get().unwrap(),
 			current.filename.display(),
 			if files.mark_supported() {
@@ -346,22 +658,94 @@ impl Application {
 			},
 			current.position,
 			current.total,
-			if files.starting() { "+" } else { "" }
+			match current.filter {
+				Filter::All => "",
+				Filter::Marked => " marked",
+				Filter::Unmarked => " unmarked",
+			},
+			if files.starting() { "+" } else { "" },
+			drawing_area
+				.scale()
+				.map(|scale| format!(" {:.0}%", f64::from(scale) * 100.0))
+				.unwrap_or_default(),
+			if current.panes.len() > 1 {
+				format!(" [{}/{}]", current.active_pane + 1, current.panes.len())
+			} else {
+				String::new()
+			}
 		));
+	}
+
+	/// Pan the image with the arrow keys (or HJKL) if it's larger than the
+	/// window in that direction
+	fn key_pan(&self, event: &gdk::EventKey) -> glib::Propagation {
+		use gdk::keys::constants as key;
+
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if event
+			.state()
+			.intersects(gtk::accelerator_get_default_mod_mask())
+		{
+			return glib::Propagation::Proceed;
+		}
+
+		let (x, y) = match event.keyval() {
+			key::Left | key::KP_Left | key::h => (-1.0, 0.0),
+			key::Right | key::KP_Right | key::l => (1.0, 0.0),
+			key::Up | key::KP_Up | key::k => (0.0, -1.0),
+			key::Down | key::KP_Down | key::j => (0.0, 1.0),
+			_ => return glib::Propagation::Proceed,
+		};
 
-		if let Some(image) = current.image {
-			drawing_area.refresh(image);
+		// HJKL are also used for actions, so they only pan while the image is
+		// zoomed in and can move in that direction
+		if drawing_area.pan(x, y) {
+			glib::Propagation::Stop
+		} else {
+			glib::Propagation::Proceed
 		}
 	}
 
+	fn save_view(&self) {
+		let drawing_area = self.drawing_area.get().unwrap();
+		let files = self.files.get().unwrap();
+		let state = self.state.lock().unwrap();
+
+		files.session_view(SessionView {
+			zoom: drawing_area.zoom_mode(),
+			af_points: state.af_points,
+		});
+	}
+
 	fn files_action(&self, action: WinAction) {
 		let files = self.files.get().unwrap();
 
+		if matches!(
+			action,
+			WinAction::ViewFirst
+				| WinAction::ViewPrevious
+				| WinAction::ViewNext
+				| WinAction::ViewLast
+				| WinAction::ViewBack10
+				| WinAction::ViewForward10
+				| WinAction::ViewBack100
+				| WinAction::ViewForward100
+				| WinAction::ViewRandom
+				| WinAction::ViewPreviousPane
+				| WinAction::ViewNextPane
+		) {
+			self.pause_slideshow();
+		}
+
 		match action {
 			WinAction::ImageRotateLeft => files.orientation(Rotate::Rotate270, false),
 			WinAction::ImageRotateRight => files.orientation(Rotate::Rotate90, false),
 			WinAction::ImageFlipHorizontal => files.orientation(Rotate::Rotate0, true),
 			WinAction::ImageFlipVertical => files.orientation(Rotate::Rotate180, true),
+			WinAction::ImageSaveOrientation => files.save_orientation(),
+			WinAction::EditUndo => files.undo(),
+			WinAction::EditRedo => files.redo(),
 			WinAction::EditMark => files.mark(Mark::Set),
 			WinAction::EditToggleMark => files.mark(Mark::Toggle),
 			WinAction::EditUnmark => files.mark(Mark::Unset),
@@ -369,18 +753,330 @@ impl Application {
 			WinAction::ViewPrevious => files.navigate(Navigate::Previous),
 			WinAction::ViewNext => files.navigate(Navigate::Next),
 			WinAction::ViewLast => files.navigate(Navigate::Last),
+			WinAction::ViewBack10 => files.navigate(Navigate::Backward(10)),
+			WinAction::ViewForward10 => files.navigate(Navigate::Forward(10)),
+			WinAction::ViewBack100 => files.navigate(Navigate::Backward(100)),
+			WinAction::ViewForward100 => files.navigate(Navigate::Forward(100)),
+			WinAction::ViewRandom => files.navigate(Navigate::Random),
+			WinAction::ViewCompareOff => files.compare(1),
+			WinAction::ViewCompare2 => files.compare(2),
+			WinAction::ViewCompare3 => files.compare(3),
+			WinAction::ViewCompare4 => files.compare(4),
+			WinAction::ViewPreviousPane => files.navigate_pane(Navigate::Previous),
+			WinAction::ViewNextPane => files.navigate_pane(Navigate::Next),
 			_ => (),
 		}
 	}
 
+	/// Move to images as a number or filename is typed, returning to the
+	/// original image if it's cancelled
+	fn go_to(&self, _action: WinAction) {
+		let files = self.files.get().unwrap();
+		let window = self.window.get().unwrap();
+		let Some(original) = files.current().image else {
+			return;
+		};
+
+		self.pause_slideshow();
+
+		let dialog = gtk::Dialog::with_buttons(
+			Some("Go To"),
+			Some(window),
+			gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+			&[
+				("_Cancel", gtk::ResponseType::Cancel),
+				("_Go", gtk::ResponseType::Accept),
+			],
+		);
+		let entry = gtk::Entry::new();
+		let files_copy = files.clone();
+
+		dialog.set_default_response(gtk::ResponseType::Accept);
+		entry.set_activates_default(true);
+		entry.set_placeholder_text(Some("Image number or filename"));
+		entry.connect_changed(move |entry| files_copy.go_to(entry.text().trim()));
+		dialog.content_area().add(&entry);
+		dialog.show_all();
+
+		let response = dialog.run();
+
+		dialog.close();
+		if response != gtk::ResponseType::Accept {
+			files.select(&original);
+		}
+	}
+
+	fn trash(&self, _action: WinAction) {
+		let files = self.files.get().unwrap();
+
+		if files.confirm_trash() {
+			let window = self.window.get().unwrap();
+			let current = files.current();
+
+			if current.image.is_none() {
+				return;
+			}
+
+			let dialog = gtk::MessageDialog::new(
+				Some(window),
+				gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+				gtk::MessageType::Question,
+				gtk::ButtonsType::YesNo,
+				&format!("Move {} to the trash?", current.filename.display()),
+			);
+			let response = dialog.run();
+
+			dialog.close();
+			if response != gtk::ResponseType::Yes {
+				return;
+			}
+		}
+
+		files.trash();
+	}
+
+	/// List the files that couldn't be opened or decoded, and why
+	///
+	/// Images that haven't been decoded yet are checked while the list is
+	/// shown, and it's updated as they're checked
+	fn failed_files(&self, _action: WinAction) {
+		let files = self.files.get().unwrap();
+		let window = self.window.get().unwrap();
+		let cancel = Cancel::default();
+		let remaining = files.check_failures(&cancel);
+		let dialog = gtk::Dialog::with_buttons(
+			Some("Failed Files"),
+			Some(window),
+			gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+			&[("_Close", gtk::ResponseType::Close)],
+		);
+		let scrolled = gtk::ScrolledWindow::builder()
+			.min_content_width(640)
+			.min_content_height(320)
+			.vexpand(true)
+			.build();
+		let view = gtk::TextView::builder()
+			.editable(false)
+			.monospace(true)
+			.wrap_mode(gtk::WrapMode::WordChar)
+			.build();
+
+		let update = {
+			let files = files.clone();
+			let buffer = view.buffer().unwrap();
+			let cancel = cancel.clone();
+
+			move || {
+				let failures = files.failures();
+				let checking = remaining.load(atomic::Ordering::Acquire);
+				let mut text = String::new();
+
+				for (filename, error) in &failures {
+					writeln!(text, "{}: {error}", filename.display()).unwrap();
+				}
+
+				if checking > 0 {
+					writeln!(text, "Checking {checking} images...").unwrap();
+				} else if failures.is_empty() {
+					text.push_str("No files have failed");
+				}
+
+				buffer.set_text(&text);
+
+				if checking > 0 && !cancel.cancelled() {
+					glib::ControlFlow::Continue
+				} else {
+					glib::ControlFlow::Break
+				}
+			}
+		};
+
+		if update().is_continue() {
+			glib::timeout_add_local(Duration::from_millis(250), update);
+		}
+
+		scrolled.add(&view);
+		dialog.content_area().add(&scrolled);
+		dialog.show_all();
+		dialog.run();
+		cancel.cancel();
+		dialog.close();
+	}
+
 	fn zoom_action(&self, action: WinAction) {
 		let drawing_area = self.drawing_area.get().unwrap();
 
 		match action {
+			WinAction::ViewZoomIn => drawing_area.zoom_in(),
+			WinAction::ViewZoomOut => drawing_area.zoom_out(),
+			WinAction::ViewZoom25 => drawing_area.zoom_to(Sf64::try_from(0.25).unwrap()),
+			WinAction::ViewZoom50 => drawing_area.zoom_to(Sf64::try_from(0.5).unwrap()),
+			WinAction::ViewZoom200 => drawing_area.zoom_to(Sf64::try_from(2.0).unwrap()),
+			WinAction::ViewZoom400 => drawing_area.zoom_to(Sf64::try_from(4.0).unwrap()),
 			WinAction::ViewZoomActual => drawing_area.zoom_actual(),
 			WinAction::ViewZoomFit => drawing_area.zoom_fit(),
+			WinAction::ViewZoomFitWidth => drawing_area.zoom_fit_to(Fit::Width),
+			WinAction::ViewZoomFitHeight => drawing_area.zoom_fit_to(Fit::Height),
+			WinAction::ViewZoomFill => drawing_area.zoom_fit_to(Fit::Fill),
 			_ => (),
 		}
+
+		self.save_view();
+	}
+
+	fn view_lock_zoom(&self, action: &SimpleAction, value: Option<&Variant>) {
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if let Some(value) = value {
+			action.set_state(value);
+			drawing_area.lock_zoom(value.get().unwrap());
+		}
+	}
+
+	fn view_slideshow(&self, action: &SimpleAction, value: Option<&Variant>) {
+		let files = self.files.get().unwrap();
+		let window = self.window.get().unwrap();
+
+		if let Some(value) = value {
+			let enable: bool = value.get().unwrap();
+			let mut state = self.state.lock().unwrap();
+			let previous = state.slideshow.take();
+
+			if enable {
+				state.slideshow = Some(Slideshow {
+					paused: false,
+					timer: None,
+					leave_full_screen: !state.full_screen,
+				});
+			}
+
+			let shuffle = enable && state.slideshow_options.shuffle;
+			drop(state);
+
+			action.set_state(value);
+			self.view_pause_slideshow_action
+				.get()
+				.unwrap()
+				.set_state(&false.to_variant());
+
+			if let Some(previous) = previous {
+				if let Some(timer) = previous.timer {
+					timer.remove();
+				}
+
+				if previous.leave_full_screen && !enable {
+					window.unfullscreen();
+				}
+			}
+
+			if enable {
+				window.fullscreen();
+			}
+
+			files.shuffle(shuffle);
+			self.slideshow_timer();
+		}
+	}
+
+	fn view_pause_slideshow(&self, action: &SimpleAction, value: Option<&Variant>) {
+		if let Some(value) = value {
+			let mut state = self.state.lock().unwrap();
+
+			// Only a running slideshow can be paused
+			let Some(slideshow) = &mut state.slideshow else {
+				return;
+			};
+
+			slideshow.paused = value.get().unwrap();
+			if let Some(timer) = slideshow.timer.take() {
+				timer.remove();
+			}
+			drop(state);
+
+			action.set_state(value);
+			self.slideshow_timer();
+		}
+	}
+
+	fn pause_slideshow(&self) {
+		if self.state.lock().unwrap().slideshow.is_some() {
+			self.view_pause_slideshow_action
+				.get()
+				.unwrap()
+				.change_state(&true.to_variant());
+		}
+	}
+
+	fn view_slideshow_loop(&self, action: &SimpleAction, value: Option<&Variant>) {
+		if let Some(value) = value {
+			action.set_state(value);
+			self.state.lock().unwrap().slideshow_options.repeat = value.get().unwrap();
+		}
+	}
+
+	fn view_slideshow_shuffle(&self, action: &SimpleAction, value: Option<&Variant>) {
+		let files = self.files.get().unwrap();
+
+		if let Some(value) = value {
+			let mut state = self.state.lock().unwrap();
+
+			action.set_state(value);
+			state.slideshow_options.shuffle = value.get().unwrap();
+
+			if state.slideshow.is_some() {
+				files.shuffle(state.slideshow_options.shuffle);
+			}
+		}
+	}
+
+	/// Start the timer to move to the next image in the slideshow, once the
+	/// current image has been loaded
+	fn slideshow_timer(&self) {
+		let files = self.files.get().unwrap();
+		let mut state = self.state.lock().unwrap();
+
+		let Some(slideshow) = &mut state.slideshow else {
+			return;
+		};
+
+		if slideshow.paused
+			|| slideshow.timer.is_some()
+			|| !files.current().image.is_some_and(|image| image.loaded())
+		{
+			return;
+		}
+
+		let self_ref = self.downgrade();
+
+		slideshow.timer = Some(glib::timeout_add_local_once(
+			files.slideshow_interval(),
+			move || {
+				if let Some(app) = self_ref.upgrade() {
+					app.slideshow_next();
+				}
+			},
+		));
+	}
+
+	fn slideshow_next(&self) {
+		let files = self.files.get().unwrap();
+		let mut state = self.state.lock().unwrap();
+		let repeat = state.slideshow_options.repeat;
+
+		let Some(slideshow) = &mut state.slideshow else {
+			return;
+		};
+
+		// The timer has been removed by returning from its callback
+		slideshow.timer = None;
+		drop(state);
+
+		if !files.slideshow_next(repeat) {
+			self.view_slideshow_action
+				.get()
+				.unwrap()
+				.change_state(&false.to_variant());
+		}
 	}
 
 	fn view_fullscreen(&self, _action: &SimpleAction, value: Option<&Variant>) {
@@ -403,6 +1099,130 @@ impl Application {
 			state.af_points = value.get().unwrap();
 			action.set_state(value);
 			drawing_area.af_points(state.af_points);
+			drop(state);
+
+			self.save_view();
+		}
+	}
+
+	fn view_pixel_inspector(&self, action: &SimpleAction, value: Option<&Variant>) {
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if let Some(value) = value {
+			action.set_state(value);
+			self.view_freeze_pixel_action
+				.get()
+				.unwrap()
+				.set_state(&false.to_variant());
+			drawing_area.pixel_inspector(value.get().unwrap());
+			self.update_zoom();
+		}
+	}
+
+	fn view_freeze_pixel(&self, action: &SimpleAction, value: Option<&Variant>) {
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if let Some(value) = value {
+			action.set_state(value);
+			drawing_area.freeze_pixel_inspector(value.get().unwrap());
+		}
+	}
+
+	fn view_loupe_100(&self, action: &SimpleAction, value: Option<&Variant>) {
+		self.view_loupe(action, value, Sf64::actual());
+	}
+
+	fn view_loupe_200(&self, action: &SimpleAction, value: Option<&Variant>) {
+		self.view_loupe(action, value, Sf64::try_from(2.0).unwrap());
+	}
+
+	fn view_loupe(&self, action: &SimpleAction, value: Option<&Variant>, scale: Sf64) {
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if let Some(value) = value {
+			let enable: bool = value.get().unwrap();
+
+			// Only one loupe scale can be active at a time
+			for other in [&self.view_loupe_100_action, &self.view_loupe_200_action] {
+				other.get().unwrap().set_state(&false.to_variant());
+			}
+			action.set_state(value);
+
+			drawing_area.loupe(enable.then_some(scale));
+			self.update_zoom();
+		}
+	}
+
+	/// Load the visible images at full resolution when they're drawn larger
+	/// than the size that they were preloaded at, or while the pixel inspector
+	/// is showing exact values
+	fn update_zoom(&self) {
+		let window = self.window.get().unwrap();
+		let drawing_area = self.drawing_area.get().unwrap();
+		let files = self.files.get().unwrap();
+		let loupe = drawing_area.loupe_scale().map_or(0.0, f64::from);
+		let inspector = if drawing_area.pixel_inspector_enabled() {
+			1.0
+		} else {
+			0.0
+		};
+
+		// The loupe scale is already in device pixels
+		files.zoom(drawing_area.scale().map(|scale| {
+			(f64::from(scale) * f64::from(window.scale_factor()))
+				.max(loupe)
+				.max(inspector)
+		}));
+	}
+
+	/// Preload images at the size of the monitor that the window is on
+	fn update_display_size(&self) {
+		let window = self.window.get().unwrap();
+		let files = self.files.get().unwrap();
+
+		if let Some(monitor) = window
+			.window()
+			.and_then(|gdk_window| window.display().monitor_at_window(&gdk_window))
+		{
+			let geometry = monitor.geometry();
+			let width = geometry.width() * monitor.scale_factor();
+			let height = geometry.height() * monitor.scale_factor();
+
+			if let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height)) {
+				files.set_display_size(DimensionsU32::from((width, height)));
+			}
+		}
+	}
+
+	fn copy_pixel(&self, _action: WinAction) {
+		let drawing_area = self.drawing_area.get().unwrap();
+
+		if let Some(text) = drawing_area.pixel_text() {
+			gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&text);
+		}
+	}
+
+	fn view_marked(&self, action: &SimpleAction, value: Option<&Variant>) {
+		self.view_filter(action, value, Filter::Marked);
+	}
+
+	fn view_unmarked(&self, action: &SimpleAction, value: Option<&Variant>) {
+		self.view_filter(action, value, Filter::Unmarked);
+	}
+
+	fn view_filter(&self, action: &SimpleAction, value: Option<&Variant>, filter: Filter) {
+		let files = self.files.get().unwrap();
+
+		if let Some(value) = value {
+			let enable: bool = value.get().unwrap();
+
+			// Only one filter can be active at a time
+			for other in [&self.view_marked_action, &self.view_unmarked_action] {
+				other.get().unwrap().set_state(&false.to_variant());
+			}
+			action.set_state(value);
+
+			files.filter(if enable { filter } else { Filter::All });
 		}
 	}
 
@@ -418,6 +1238,15 @@ impl Application {
 				.unwrap()
 				.set_state(&state.full_screen.to_variant());
 			window.set_show_menubar(!state.full_screen);
+
+			// Leaving full screen stops the slideshow
+			if !full_screen && state.slideshow.is_some() {
+				drop(state);
+				self.view_slideshow_action
+					.get()
+					.unwrap()
+					.change_state(&false.to_variant());
+			}
 		}
 	}
 
@@ -472,6 +1301,27 @@ impl ApplicationImpl for Application {
 			icon_size * 3,
 		)));
 
+		let key_ref = self.downgrade();
+
+		// Handled before the accelerators so that the same keys can be used
+		// for actions when the image isn't zoomed in
+		window.connect_key_press_event(move |_, event| -> glib::Propagation {
+			match key_ref.upgrade() {
+				Some(app) => app.key_pan(event),
+				None => glib::Propagation::Proceed,
+			}
+		});
+
+		let configure_ref = self.downgrade();
+
+		// The window may have moved to a different monitor
+		window.connect_configure_event(move |_, _| {
+			if let Some(app) = configure_ref.upgrade() {
+				app.update_display_size();
+			}
+			false
+		});
+
 		window.connect_window_state_event(move |_, event| -> glib::Propagation {
 			if let Some(app) = self_ref.upgrade() {
 				let full_screen = event
@@ -490,6 +1340,19 @@ impl ApplicationImpl for Application {
 				|widget| window.add(widget),
 			))
 			.unwrap();
+
+		let files = self.files.get().unwrap();
+		let drawing_area = self.drawing_area.get().unwrap();
+		let app_ref = self.downgrade();
+
+		drawing_area.scale_filters(files.enlarge_filter(), files.reduce_filter());
+		drawing_area.pan_step(files.pan_step());
+		drawing_area.connect_scale_changed(move || {
+			if let Some(app) = app_ref.upgrade() {
+				app.update_title(&app.files.get().unwrap().current());
+				app.update_zoom();
+			}
+		});
 	}
 
 	/// The command line is ignored here, see `CommandLineArgs::parse()`
@@ -503,6 +1366,13 @@ impl ApplicationImpl for Application {
 
 		let window = self.window.get().unwrap();
 
+		if self.files.get().unwrap().restored_view().af_points {
+			self.view_af_points_action
+				.get()
+				.unwrap()
+				.change_state(&true.to_variant());
+		}
+
 		self.refresh();
 
 		window.maximize();
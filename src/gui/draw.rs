@@ -18,42 +18,127 @@
 
 use crate::{
 	fiv::{
-		Image, Orientation, Rotate,
-		numeric::{DimensionsF64, PointF64, PointI32, Sf64, XYf64, Xf64, Yf64, Zero},
+		AFPoint, Failure, Image, Orientation, Partial, Rotate, ZoomMode,
+		numeric::{DimensionsF64, PointF64, PointI32, Sf64, XYf64, Xf64, Xu32, Yf64, Yu32, Zero},
 	},
 	nutype_const,
 };
+use gtk::glib::once_cell::unsync::OnceCell;
 use gtk::{cairo, gdk, glib, prelude::*};
 use log::trace;
 use std::{
+	cell::Cell,
+	fmt::Write as _,
+	mem,
 	rc::Rc,
 	sync::{Arc, Mutex},
 	time::Instant,
 };
 
 nutype_const!(SCROLL_ZOOM_FACTOR, Sf64, 1.10);
+nutype_const!(KEY_ZOOM_FACTOR, Sf64, std::f64::consts::SQRT_2);
 
 // Don't allow zooming too far in/out, it'll cause errors in cairo, and
 // subnormal numbers are considered non-finite
 nutype_const!(MIN_ZOOM, Sf64, 1.0 / u32::MAX as f64);
 nutype_const!(MAX_ZOOM, Sf64, u32::MAX as f64);
 
-#[derive(Debug)]
+#[derive(derive_more::Debug)]
 pub struct DrawingArea {
 	widget: gtk::DrawingArea,
 	drag_gesture: gtk::GestureDrag,
 	zoom_gesture: gtk::GestureZoom,
-	image_draw: Rc<Mutex<ImageDraw>>,
+	panes: Rc<Mutex<Panes>>,
+
+	/// Scale of the image in the active pane when it was last drawn
+	scale: Cell<Option<Sf64>>,
+	#[debug(skip)]
+	scale_changed: OnceCell<Box<dyn Fn()>>,
+
+	/// Distance to move the image for each pan step
+	pan_step: Cell<f64>,
+	inspector: Cell<Inspector>,
+
+	/// Scale of the loupe, if it's enabled
+	loupe: Cell<Option<Sf64>>,
+
+	/// Position of the pointer from the last motion event
+	motion: Cell<Option<PointF64>>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Loupe {
+	/// Position of the pointer in the drawing area, or in the pane when it's
+	/// passed to `ImageDraw::draw()`
+	pointer: PointF64,
+	scale: Sf64,
+}
+
+/// Readout of the pixel under the pointer
+#[derive(Debug, Default, Copy, Clone)]
+struct Inspector {
+	enabled: bool,
+
+	/// Keep showing the same pixel when the pointer moves
+	frozen: bool,
+
+	/// Position of the pointer in the drawing area
+	pointer: Option<PointF64>,
+}
+
+/// How the image is scaled to the window when it hasn't been zoomed to a
+/// specific scale, which is recalculated whenever the window changes size
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Fit {
+	/// The whole image is visible
+	#[default]
+	Best,
+	Width,
+	Height,
+
+	/// The window is filled in both directions
+	Fill,
+}
+
+/// Images drawn side by side, with the same zoom and drag operations applied
+/// to all of them
+#[derive(Debug)]
+struct Panes {
+	image_draws: Vec<ImageDraw>,
+	active: usize,
 }
 
 #[derive(Debug)]
 struct ImageDraw {
 	startup: Startup,
 	image: Option<Arc<Image>>,
-	waiting: bool,
+	waiting: Waiting,
 	zoom: Zoom,
 	orientation: Orientation,
 	af_points: bool,
+
+	/// Keep the zoom scale and position when the image changes
+	lock_zoom: bool,
+
+	/// Scale that the image was last drawn at
+	scale: Option<Sf64>,
+	enlarge_filter: cairo::Filter,
+	reduce_filter: cairo::Filter,
+
+	/// Zoom mode to apply when the image is next drawn
+	restore_zoom: Option<ZoomMode>,
+}
+
+/// What was missing when the image was last drawn, so that it can be drawn
+/// again when it has been loaded
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+enum Waiting {
+	#[default]
+	Nothing,
+	Image,
+
+	/// The image was drawn enlarged from the size that it was preloaded at
+	FullResolution,
 }
 
 #[derive(Debug)]
@@ -65,9 +150,15 @@ struct Startup {
 #[derive(Debug, Default)]
 pub struct Zoom {
 	scale: Option<Sf64>,
+
+	/// Only used if there's no scale
+	fit: Fit,
 	position: PointF64,
 	drag_offset: PointF64,
 	tolerance: PointF64,
+
+	/// Scale when the current zoom gesture began
+	gesture_scale: Option<Sf64>,
 }
 
 #[derive(Debug)]
@@ -82,10 +173,15 @@ impl ImageDraw {
 		Self {
 			startup: Startup::new(startup),
 			image: None,
-			waiting: false,
+			waiting: Waiting::Nothing,
 			zoom: Zoom::default(),
 			orientation: Orientation::default(),
 			af_points: false,
+			lock_zoom: false,
+			scale: None,
+			enlarge_filter: cairo::Filter::Nearest,
+			reduce_filter: cairo::Filter::Good,
+			restore_zoom: None,
 		}
 	}
 }
@@ -107,21 +203,23 @@ impl DrawingArea {
 				widget,
 				drag_gesture,
 				zoom_gesture,
-				image_draw: Rc::new(Mutex::new(ImageDraw::new(startup))),
+				panes: Rc::new(Mutex::new(Panes::new(startup))),
+				scale: Cell::new(None),
+				scale_changed: OnceCell::new(),
+				pan_step: Cell::new(50.0),
+				inspector: Cell::new(Inspector::default()),
+				loupe: Cell::new(None),
+				motion: Cell::new(None),
 			})
 		};
 
 		{
-			let draw_image_ref = Rc::downgrade(&drawing_area.image_draw);
+			let draw_ref = Rc::downgrade(&drawing_area);
 			drawing_area
 				.widget
 				.connect_draw(move |area, context| -> glib::Propagation {
-					if let Some(draw_image) = draw_image_ref.upgrade() {
-						draw_image.lock().unwrap().draw(
-							&area.allocation(),
-							area.scale_factor(),
-							context,
-						);
+					if let Some(draw_copy) = draw_ref.upgrade() {
+						draw_copy.draw(area, context);
 					}
 
 					glib::Propagation::Proceed
@@ -140,7 +238,20 @@ impl DrawingArea {
 					glib::Propagation::Proceed
 				});
 
-			drawing_area.widget.add_events(gdk::EventMask::SCROLL_MASK);
+			drawing_area
+				.widget
+				.add_events(gdk::EventMask::SCROLL_MASK | gdk::EventMask::SMOOTH_SCROLL_MASK);
+		}
+
+		drawing_area.connect_pointer();
+
+		{
+			let draw_ref = Rc::downgrade(&drawing_area);
+			drawing_area.zoom_gesture.connect_begin(move |_, _| {
+				if let Some(draw_copy) = draw_ref.upgrade() {
+					draw_copy.zoom_gesture_begin();
+				}
+			});
 		}
 
 		{
@@ -149,7 +260,7 @@ impl DrawingArea {
 				.zoom_gesture
 				.connect_scale_changed(move |_, scale| {
 					if let Some(draw_copy) = draw_ref.upgrade() {
-						draw_copy.zoom_adjust(Sf64::try_from(scale).unwrap());
+						draw_copy.zoom_gesture(scale);
 					}
 				});
 		}
@@ -191,41 +302,233 @@ impl DrawingArea {
 		drawing_area
 	}
 
-	pub fn refresh(&self, image: Arc<Image>) {
-		if self.image_draw.lock().unwrap().refresh(image) {
+	fn connect_pointer(self: &Rc<Self>) {
+		{
+			let draw_ref = Rc::downgrade(self);
+			self.widget
+				.connect_motion_notify_event(move |_, event| -> glib::Propagation {
+					if let Some(draw_copy) = draw_ref.upgrade() {
+						draw_copy.pointer_moved(Some(event.position().into()));
+					}
+
+					glib::Propagation::Proceed
+				});
+		}
+
+		{
+			let draw_ref = Rc::downgrade(self);
+			self.widget
+				.connect_leave_notify_event(move |_, _| -> glib::Propagation {
+					if let Some(draw_copy) = draw_ref.upgrade() {
+						draw_copy.pointer_moved(None);
+					}
+
+					glib::Propagation::Proceed
+				});
+
+			self.widget.add_events(
+				gdk::EventMask::POINTER_MOTION_MASK | gdk::EventMask::LEAVE_NOTIFY_MASK,
+			);
+		}
+	}
+
+	fn draw(&self, area: &gtk::DrawingArea, context: &cairo::Context) {
+		let scale = {
+			let mut panes = self.panes.lock().unwrap();
+			let allocation = area.allocation();
+			let inspector = self.inspector.get();
+			let loupe = self
+				.loupe
+				.get()
+				.zip(self.motion.get())
+				.map(|(scale, pointer)| Loupe { pointer, scale });
+
+			panes.draw(&allocation, area.scale_factor(), context, loupe);
+
+			if inspector.enabled
+				&& let Some(pointer) = inspector.pointer
+				&& let Some(text) = panes.inspect(&allocation, pointer)
+			{
+				Self::draw_inspector(
+					&allocation,
+					context,
+					&if inspector.frozen {
+						format!("{text} [frozen]")
+					} else {
+						text
+					},
+				);
+			}
+
+			panes.active().scale
+		};
+
+		if self.scale.replace(scale) != scale
+			&& let Some(func) = self.scale_changed.get()
+		{
+			func();
+		}
+	}
+
+	fn draw_inspector(allocation: &gtk::Allocation, context: &cairo::Context, text: &str) {
+		const PADDING: f64 = 4.0;
+
+		context.save().unwrap();
+		context.select_font_face(
+			"monospace",
+			cairo::FontSlant::Normal,
+			cairo::FontWeight::Normal,
+		);
+		context.set_font_size(13.0);
+
+		let font = context.font_extents().unwrap();
+		let width = context.text_extents(text).unwrap().x_advance() + PADDING * 2.0;
+		let height = font.ascent() + font.descent() + PADDING * 2.0;
+		let bottom = f64::from(allocation.height());
+
+		context.set_source_rgba(0.0, 0.0, 0.0, 0.75);
+		context.rectangle(0.0, bottom - height, width, height);
+		context.fill().unwrap();
+
+		context.set_source_rgb(1.0, 1.0, 1.0);
+		context.move_to(PADDING, bottom - PADDING - font.descent());
+		context.show_text(text).unwrap();
+		context.restore().unwrap();
+	}
+
+	fn pointer_moved(&self, pointer: Option<PointF64>) {
+		let mut inspector = self.inspector.get();
+		let mut redraw = self.loupe.get().is_some();
+
+		self.motion.set(pointer);
+
+		if inspector.enabled && !inspector.frozen {
+			inspector.pointer = pointer;
+			self.inspector.set(inspector);
+			redraw = true;
+		}
+
+		if redraw {
+			self.redraw();
+		}
+	}
+
+	/// Show part of the image at a fixed scale around the pointer
+	pub fn loupe(&self, scale: Option<Sf64>) {
+		self.loupe.set(scale);
+		self.motion.set(self.pointer().map(PointF64::from));
+		self.redraw();
+	}
+
+	pub fn loupe_scale(&self) -> Option<Sf64> {
+		self.loupe.get()
+	}
+
+	pub fn pixel_inspector_enabled(&self) -> bool {
+		self.inspector.get().enabled
+	}
+
+	pub fn pixel_inspector(&self, enable: bool) {
+		self.inspector.set(Inspector {
+			enabled: enable,
+			frozen: false,
+			pointer: self.pointer().map(PointF64::from),
+		});
+		self.redraw();
+	}
+
+	pub fn freeze_pixel_inspector(&self, freeze: bool) {
+		let mut inspector = self.inspector.get();
+
+		inspector.frozen = freeze;
+		if !freeze {
+			inspector.pointer = self.pointer().map(PointF64::from);
+		}
+		self.inspector.set(inspector);
+		self.redraw();
+	}
+
+	/// Position and value of the pixel shown by the inspector, or under the
+	/// pointer if the inspector isn't enabled
+	pub fn pixel_text(&self) -> Option<String> {
+		let inspector = self.inspector.get();
+		let pointer = if inspector.enabled {
+			inspector.pointer
+		} else {
+			self.pointer().map(PointF64::from)
+		}?;
+
+		self.panes
+			.lock()
+			.unwrap()
+			.inspect(&self.widget.allocation(), pointer)
+	}
+
+	/// Call a function when the scale of the image in the active pane changes
+	pub fn connect_scale_changed<F: Fn() + 'static>(&self, func: F) {
+		assert!(
+			self.scale_changed.set(Box::new(func)).is_ok(),
+			"Only one scale changed function is supported"
+		);
+	}
+
+	pub fn scale(&self) -> Option<Sf64> {
+		self.scale.get()
+	}
+
+	/// Refresh the images in each pane
+	pub fn refresh(&self, images: &[Option<Arc<Image>>], active: usize) {
+		if self.panes.lock().unwrap().refresh(images, active) {
 			self.redraw();
 		}
 	}
 
 	pub fn drag_begin(&self, start: PointF64) {
-		let mut image_draw = self.image_draw.lock().unwrap();
+		let mut panes = self.panes.lock().unwrap();
 		let window = self.widget.window().unwrap();
 		let display = window.display();
 
 		window.set_cursor(gdk::Cursor::for_display(&display, gdk::CursorType::Fleur).as_ref());
-		image_draw.drag_begin(&self.widget.allocation(), start);
+		panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.drag_begin(allocation, start)
+		});
 	}
 
 	pub fn drag_update(&self, offset: PointF64) {
-		let mut image_draw = self.image_draw.lock().unwrap();
+		let mut panes = self.panes.lock().unwrap();
 
-		if image_draw.drag_update(&self.widget.allocation(), offset) {
+		if panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.drag_update(allocation, offset)
+		}) {
 			self.redraw();
 		}
 	}
 
 	pub fn drag_end(&self, offset: PointF64) {
-		let mut image_draw = self.image_draw.lock().unwrap();
+		let mut panes = self.panes.lock().unwrap();
 		let window = self.widget.window().unwrap();
 
 		window.set_cursor(None);
-		if image_draw.drag_end(&self.widget.allocation(), offset) {
+		if panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.drag_end(allocation, offset)
+		}) {
 			self.redraw();
 		}
 	}
 
 	fn scroll(&self, event: &gdk::EventScroll) {
+		// Shift changes vertical scrolling to horizontal panning
+		let shift = event.state().contains(gdk::ModifierType::SHIFT_MASK);
+
 		match event.direction() {
+			gdk::ScrollDirection::Up if shift => {
+				self.pan(-1.0, 0.0);
+			}
+
+			gdk::ScrollDirection::Down if shift => {
+				self.pan(1.0, 0.0);
+			}
+
 			gdk::ScrollDirection::Up => {
 				self.zoom_adjust(SCROLL_ZOOM_FACTOR);
 			}
@@ -234,34 +537,176 @@ impl DrawingArea {
 				self.zoom_adjust(1.0 / SCROLL_ZOOM_FACTOR);
 			}
 
+			gdk::ScrollDirection::Left => {
+				self.pan(-1.0, 0.0);
+			}
+
+			gdk::ScrollDirection::Right => {
+				self.pan(1.0, 0.0);
+			}
+
+			gdk::ScrollDirection::Smooth => {
+				let (x, y) = event.delta();
+
+				if shift {
+					self.pan(x + y, 0.0);
+				} else {
+					self.pan(x, 0.0);
+					self.zoom_adjust(scroll_zoom_factor(y));
+				}
+			}
+
 			_ => (),
 		}
 	}
 
+	pub fn pan_step(&self, step: f64) {
+		self.pan_step.set(step);
+	}
+
+	/// Move the view of the image by a number of pan steps in each direction,
+	/// returning false if the image fits in the window in those directions
+	pub fn pan(&self, x: f64, y: f64) -> bool {
+		let mut panes = self.panes.lock().unwrap();
+		let step = self.pan_step.get();
+		let offset = PointF64::from((-x * step, -y * step));
+
+		if panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.pan(allocation, offset)
+		}) {
+			self.redraw();
+			true
+		} else {
+			false
+		}
+	}
+
 	pub fn zoom_actual(&self) {
-		let mut image_draw = self.image_draw.lock().unwrap();
+		let mut panes = self.panes.lock().unwrap();
+		let allocation = self.widget.allocation();
+		let pointer = panes.pointer(&allocation, self.pointer());
+
+		if panes.each(&allocation, |image_draw, allocation| {
+			image_draw.zoom_actual(allocation, pointer)
+		}) {
+			self.redraw();
+		}
+	}
+
+	pub fn zoom_in(&self) {
+		self.zoom_adjust(KEY_ZOOM_FACTOR);
+	}
+
+	pub fn zoom_out(&self) {
+		self.zoom_adjust(1.0 / KEY_ZOOM_FACTOR);
+	}
+
+	pub fn zoom_to(&self, scale: Sf64) {
+		let mut panes = self.panes.lock().unwrap();
+		let allocation = self.widget.allocation();
+		let pointer = panes.pointer(&allocation, self.pointer());
+
+		if panes.each(&allocation, |image_draw, allocation| {
+			image_draw.zoom_to(allocation, pointer, scale)
+		}) {
+			self.redraw();
+		}
+	}
+
+	fn zoom_gesture_begin(&self) {
+		let mut panes = self.panes.lock().unwrap();
+
+		panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.zoom_gesture_begin(allocation)
+		});
+	}
+
+	/// Zoom by the ratio of the current distance between the touch points to
+	/// the distance when the gesture began
+	fn zoom_gesture(&self, ratio: f64) {
+		let mut panes = self.panes.lock().unwrap();
+		let allocation = self.widget.allocation();
+		let pointer = panes.pointer(&allocation, self.pointer());
 
-		if image_draw.zoom_actual(&self.widget.allocation(), self.pointer()) {
+		if panes.each(&allocation, |image_draw, allocation| {
+			image_draw.zoom_gesture(allocation, pointer, ratio)
+		}) {
+			self.redraw();
+		}
+	}
+
+	pub fn zoom_fit_to(&self, fit: Fit) {
+		let mut panes = self.panes.lock().unwrap();
+
+		if panes.each(&self.widget.allocation(), |image_draw, allocation| {
+			image_draw.zoom_fit_to(allocation, fit)
+		}) {
 			self.redraw();
 		}
 	}
 
 	pub fn zoom_adjust(&self, scale: Sf64) {
-		let mut image_draw = self.image_draw.lock().unwrap();
+		let mut panes = self.panes.lock().unwrap();
+		let allocation = self.widget.allocation();
+		let pointer = panes.pointer(&allocation, self.pointer());
 
-		if image_draw.zoom_adjust(&self.widget.allocation(), self.pointer(), scale) {
+		if panes.each(&allocation, |image_draw, allocation| {
+			image_draw.zoom_adjust(allocation, pointer, scale)
+		}) {
 			self.redraw();
 		}
 	}
 
 	pub fn zoom_fit(&self) {
-		if self.image_draw.lock().unwrap().zoom_fit() {
+		let mut panes = self.panes.lock().unwrap();
+
+		if panes.each(&self.widget.allocation(), |image_draw, _| {
+			image_draw.zoom_fit()
+		}) {
+			self.redraw();
+		}
+	}
+
+	pub fn zoom_mode(&self) -> ZoomMode {
+		self.panes.lock().unwrap().active().zoom_mode()
+	}
+
+	/// Restore the zoom mode for the current image, centred in the window
+	pub fn restore_zoom(&self, mode: ZoomMode) {
+		let mut panes = self.panes.lock().unwrap();
+
+		if panes.each(&self.widget.allocation(), |image_draw, _| {
+			image_draw.restore_zoom(mode)
+		}) {
 			self.redraw();
 		}
 	}
 
 	pub fn af_points(&self, enable: bool) {
-		if self.image_draw.lock().unwrap().af_points(enable) {
+		let mut panes = self.panes.lock().unwrap();
+
+		if panes.each(&self.widget.allocation(), |image_draw, _| {
+			image_draw.af_points(enable)
+		}) {
+			self.redraw();
+		}
+	}
+
+	pub fn lock_zoom(&self, enable: bool) {
+		let mut panes = self.panes.lock().unwrap();
+
+		panes.each(&self.widget.allocation(), |image_draw, _| {
+			image_draw.lock_zoom = enable;
+			false
+		});
+	}
+
+	pub fn scale_filters(&self, enlarge: cairo::Filter, reduce: cairo::Filter) {
+		let mut panes = self.panes.lock().unwrap();
+
+		if panes.each(&self.widget.allocation(), |image_draw, _| {
+			image_draw.scale_filters(enlarge, reduce)
+		}) {
 			self.redraw();
 		}
 	}
@@ -274,7 +719,8 @@ impl DrawingArea {
 		}
 	}
 
-	fn pointer(&self) -> PointI32 {
+	/// Returns `None` if the pointer is outside the drawing area
+	fn pointer(&self) -> Option<PointI32> {
 		let window = self.widget.window().unwrap();
 		let seat = self.widget.display().default_seat().unwrap();
 		let device_position = window.device_position(&seat.pointer().unwrap());
@@ -289,12 +735,185 @@ impl DrawingArea {
 				window_position.x.into(),
 				window_position.y.into(),
 			)
-			.unwrap()
-			.into()
+			.filter(|&(x, y)| {
+				let allocation = self.widget.allocation();
+
+				(0..allocation.width()).contains(&x) && (0..allocation.height()).contains(&y)
+			})
+			.map(PointI32::from)
+	}
+}
+
+impl Panes {
+	pub fn new(startup: Instant) -> Self {
+		Self {
+			image_draws: vec![ImageDraw::new(startup)],
+			active: 0,
+		}
+	}
+
+	pub fn active(&self) -> &ImageDraw {
+		&self.image_draws[self.active]
+	}
+
+	pub fn refresh(&mut self, images: &[Option<Arc<Image>>], active: usize) -> bool {
+		let mut changed = self.image_draws.len() != images.len() || self.active != active;
+
+		// New panes use the same settings as the existing panes
+		while self.image_draws.len() < images.len() {
+			let image_draw = self.image_draws[0].new_pane();
+
+			self.image_draws.push(image_draw);
+		}
+		self.image_draws.truncate(images.len().max(1));
+		self.active = active;
+
+		for (image_draw, image) in self.image_draws.iter_mut().zip(images) {
+			changed |= match image {
+				Some(image) => image_draw.refresh(image.clone()),
+				None => image_draw.clear(),
+			};
+		}
+
+		changed
+	}
+
+	/// Allocation of a pane, relative to the drawing area
+	fn pane_allocation(&self, allocation: &gtk::Allocation, index: usize) -> gtk::Allocation {
+		let count = i32::try_from(self.image_draws.len()).unwrap();
+		let index = i32::try_from(index).unwrap();
+		let left = allocation.width() * index / count;
+		let right = allocation.width() * (index + 1) / count;
+
+		gtk::Allocation::new(left, 0, right - left, allocation.height())
+	}
+
+	/// Run a function for every pane with the allocation of the pane,
+	/// returning true if any of them need to be redrawn
+	pub fn each<F: FnMut(&mut ImageDraw, &gtk::Allocation) -> bool>(
+		&mut self,
+		allocation: &gtk::Allocation,
+		mut func: F,
+	) -> bool {
+		let mut redraw = false;
+
+		for index in 0..self.image_draws.len() {
+			let pane = self.pane_allocation(allocation, index);
+			let pane = gtk::Allocation::new(0, 0, pane.width(), pane.height());
+
+			redraw |= func(&mut self.image_draws[index], &pane);
+		}
+
+		redraw
+	}
+
+	/// Convert the pointer position to be relative to the pane that it's in,
+	/// or use the centre of the pane if there's no pointer
+	pub fn pointer(&self, allocation: &gtk::Allocation, pointer: Option<PointI32>) -> PointI32 {
+		let Some(pointer) = pointer else {
+			let pane = self.pane_allocation(allocation, 0);
+
+			return PointI32::from((pane.width() / 2, pane.height() / 2));
+		};
+		let x = i32::from(pointer.x);
+
+		(0..self.image_draws.len())
+			.map(|index| self.pane_allocation(allocation, index))
+			.find(|pane| x < pane.x() + pane.width())
+			.map_or(pointer, |pane| {
+				PointI32::from((x - pane.x(), i32::from(pointer.y)))
+			})
+	}
+
+	/// Describe the pixel at a position in the drawing area
+	pub fn inspect(&mut self, allocation: &gtk::Allocation, pointer: PointF64) -> Option<String> {
+		let (index, pane, pointer) = self.pane_at(allocation, pointer)?;
+
+		self.image_draws[index].inspect(&pane, pointer)
+	}
+
+	/// Find the pane at a position in the drawing area, returning its index,
+	/// its allocation and the position relative to the pane
+	fn pane_at(
+		&self,
+		allocation: &gtk::Allocation,
+		pointer: PointF64,
+	) -> Option<(usize, gtk::Allocation, PointF64)> {
+		let index = (0..self.image_draws.len()).find(|&index| {
+			let pane = self.pane_allocation(allocation, index);
+
+			f64::from(pointer.x) < f64::from(pane.x() + pane.width())
+		})?;
+		let pane = self.pane_allocation(allocation, index);
+		let offset = PointF64::from((pane.x().into(), pane.y().into()));
+
+		Some((
+			index,
+			gtk::Allocation::new(0, 0, pane.width(), pane.height()),
+			pointer - offset,
+		))
+	}
+
+	pub fn draw(
+		&mut self,
+		allocation: &gtk::Rectangle,
+		scale_factor: i32,
+		context: &cairo::Context,
+		loupe: Option<Loupe>,
+	) {
+		// The loupe is only drawn in the pane that the pointer is in
+		let loupe = loupe.and_then(|loupe| {
+			self.pane_at(allocation, loupe.pointer)
+				.map(|(index, _, pointer)| (index, Loupe { pointer, ..loupe }))
+		});
+
+		for index in 0..self.image_draws.len() {
+			let pane = self.pane_allocation(allocation, index);
+
+			context.save().unwrap();
+			context.translate(pane.x().into(), pane.y().into());
+			context.rectangle(0.0, 0.0, pane.width().into(), pane.height().into());
+			context.clip();
+			self.image_draws[index].draw(
+				&gtk::Allocation::new(0, 0, pane.width(), pane.height()),
+				scale_factor,
+				context,
+				loupe.and_then(|(loupe_index, loupe)| (loupe_index == index).then_some(loupe)),
+			);
+			context.restore().unwrap();
+		}
+
+		if self.image_draws.len() > 1 {
+			let pane = self.pane_allocation(allocation, self.active);
+
+			context.set_source_rgb(0.25, 0.5, 1.0);
+			context.set_line_width(2.0);
+			context.rectangle(
+				f64::from(pane.x()) + 1.0,
+				f64::from(pane.y()) + 1.0,
+				f64::from(pane.width()) - 2.0,
+				f64::from(pane.height()) - 2.0,
+			);
+			context.stroke().unwrap();
+		}
 	}
 }
 
 impl ImageDraw {
+	fn new_pane(&self) -> Self {
+		Self {
+			startup: Startup {
+				begin: self.startup.begin,
+				draw: true,
+			},
+			af_points: self.af_points,
+			lock_zoom: self.lock_zoom,
+			enlarge_filter: self.enlarge_filter,
+			reduce_filter: self.reduce_filter,
+			..Self::new(self.startup.begin)
+		}
+	}
+
 	pub fn refresh(&mut self, image: Arc<Image>) -> bool {
 		let changed = match &self.image {
 			Some(other) => !Arc::ptr_eq(&image, other) || self.orientation != image.orientation(),
@@ -302,16 +921,51 @@ impl ImageDraw {
 		};
 
 		if changed {
+			let previous = self
+				.image
+				.as_ref()
+				.map(|other| Self::dimensions(other, self.orientation));
+			let zoom = mem::take(&mut self.zoom);
+
 			self.orientation = image.orientation();
-			self.zoom = Zoom::default();
+			self.restore_zoom = None;
+
+			// Scale relative to the width of the image, so that the same part
+			// of the image is visible if the dimensions are different
+			if self.lock_zoom
+				&& let Some(previous) = previous
+			{
+				let current = Self::dimensions(&image, self.orientation);
+
+				self.zoom = Zoom {
+					scale: zoom.scale.map(|scale| {
+						(scale * Sf64::ratio(previous.width, current.width))
+							.clamp(MIN_ZOOM, MAX_ZOOM)
+					}),
+					fit: zoom.fit,
+					position: zoom.position,
+					..Zoom::default()
+				};
+			}
+
 			self.image = Some(image);
 
 			true
 		} else {
-			self.waiting && image.loaded()
+			match self.waiting {
+				Waiting::Nothing => false,
+				Waiting::Image => image.loaded(),
+				Waiting::FullResolution => image.full_loaded(),
+			}
 		}
 	}
 
+	pub fn clear(&mut self) -> bool {
+		self.zoom = Zoom::default();
+		self.restore_zoom = None;
+		self.image.take().is_some()
+	}
+
 	pub fn drag_begin(&mut self, allocation: &gtk::Allocation, _start: PointF64) -> bool {
 		self.zoom.drag_offset = PointF64::default();
 
@@ -340,8 +994,27 @@ impl ImageDraw {
 		self.image.is_some()
 	}
 
+	/// Move the image by an offset, if it's larger than the window in that
+	/// direction
+	pub fn pan(&mut self, allocation: &gtk::Allocation, offset: PointF64) -> bool {
+		let Some(draw_at) = self.calc_draw_position(allocation, true) else {
+			return false;
+		};
+		let output: DimensionsF64 = allocation.into();
+		let input = draw_at.dimensions * draw_at.scale;
+
+		if (offset.x == Xf64::zero() || input.width <= output.width)
+			&& (offset.y == Yf64::zero() || input.height <= output.height)
+		{
+			return false;
+		}
+
+		self.drag_begin(allocation, PointF64::default());
+		self.drag_end(allocation, offset)
+	}
+
 	pub fn zoom_actual(&mut self, allocation: &gtk::Allocation, pointer: PointI32) -> bool {
-		self.zoom(allocation, pointer, None);
+		self.zoom(allocation, pointer, |_| Sf64::actual());
 		self.image.is_some()
 	}
 
@@ -351,28 +1024,109 @@ impl ImageDraw {
 		pointer: PointI32,
 		scale: Sf64,
 	) -> bool {
-		self.zoom(allocation, pointer, Some(scale));
+		self.zoom(allocation, pointer, |current| {
+			current * scale.clamp(MIN_ZOOM, MAX_ZOOM)
+		});
+		self.image.is_some()
+	}
+
+	pub fn zoom_to(
+		&mut self,
+		allocation: &gtk::Allocation,
+		pointer: PointI32,
+		scale: Sf64,
+	) -> bool {
+		self.zoom(allocation, pointer, |_| scale);
+		self.image.is_some()
+	}
+
+	pub fn zoom_gesture_begin(&mut self, allocation: &gtk::Allocation) -> bool {
+		self.zoom.gesture_scale = self
+			.calc_draw_position(allocation, true)
+			.map(|draw_at| draw_at.scale);
+		false
+	}
+
+	pub fn zoom_gesture(
+		&mut self,
+		allocation: &gtk::Allocation,
+		pointer: PointI32,
+		ratio: f64,
+	) -> bool {
+		let Some(begin) = self.zoom.gesture_scale else {
+			return false;
+		};
+
+		self.zoom(allocation, pointer, |_| gesture_zoom_scale(begin, ratio));
+		self.image.is_some()
+	}
+
+	/// Zoom so that the image fits the window in one direction (or fills the
+	/// window for both directions), centred in the window
+	pub fn zoom_fit_to(&mut self, allocation: &gtk::Allocation, fit: Fit) -> bool {
+		self.restore_zoom = None;
+		self.fit(allocation, fit);
 		self.image.is_some()
 	}
 
 	pub fn zoom_fit(&mut self) -> bool {
 		self.zoom = Zoom::default();
+		self.restore_zoom = None;
 		self.image.is_some()
 	}
 
-	fn zoom(&mut self, allocation: &gtk::Allocation, pointer: PointI32, scale: Option<Sf64>) {
-		if let Some(draw_at) = self.calc_draw_position(allocation, true) {
-			let pointer: PointF64 = pointer.into();
-			let scale = scale.map_or(Sf64::actual(), |value| {
-				let value = value.clamp(MIN_ZOOM, MAX_ZOOM);
+	pub fn zoom_mode(&self) -> ZoomMode {
+		self.restore_zoom.unwrap_or(match self.zoom.scale {
+			Some(scale) if scale == Sf64::actual() => ZoomMode::Actual,
+			Some(_) => ZoomMode::Fit,
+			None => match self.zoom.fit {
+				Fit::Best => ZoomMode::Fit,
+				Fit::Width => ZoomMode::FitWidth,
+				Fit::Height => ZoomMode::FitHeight,
+				Fit::Fill => ZoomMode::Fill,
+			},
+		})
+	}
 
-				(draw_at.scale * value).clamp(MIN_ZOOM, MAX_ZOOM)
-			});
+	pub fn restore_zoom(&mut self, mode: ZoomMode) -> bool {
+		self.restore_zoom = Some(mode);
+		self.image.is_some()
+	}
+
+	/// Scale the image to fit the window, centred in the window
+	fn fit(&mut self, allocation: &gtk::Allocation, fit: Fit) {
+		self.zoom = Zoom {
+			fit,
+			..Zoom::default()
+		};
+
+		if let Some(draw_at) = self.calc_draw_position(allocation, false) {
+			let output: DimensionsF64 = allocation.into();
+			let input = draw_at.dimensions * draw_at.scale;
+
+			self.zoom.position = PointF64::new(
+				(output.width - input.width) / 2.0,
+				(output.height - input.height) / 2.0,
+			);
+			self.calc_draw_position(allocation, false);
+		}
+	}
+
+	/// Change the scale from the current scale, keeping the same part of
+	/// the image under the pointer
+	fn zoom<F: FnOnce(Sf64) -> Sf64>(
+		&mut self,
+		allocation: &gtk::Allocation,
+		pointer: PointI32,
+		scale: F,
+	) {
+		if let Some(draw_at) = self.calc_draw_position(allocation, true) {
+			let scale = scale(draw_at.scale).clamp(MIN_ZOOM, MAX_ZOOM);
 
 			self.zoom.scale = Some(scale);
-			self.zoom.position = pointer
-				- ((pointer - draw_at.position) / draw_at.scale * scale)
-				- self.zoom.drag_offset;
+			self.zoom.position =
+				zoom_position(pointer.into(), draw_at.position, draw_at.scale, scale)
+					- self.zoom.drag_offset;
 
 			self.calc_draw_position(allocation, false);
 		}
@@ -385,11 +1139,18 @@ impl ImageDraw {
 			.is_some_and(|image| image.metadata.af_points.is_some())
 	}
 
+	pub fn scale_filters(&mut self, enlarge: cairo::Filter, reduce: cairo::Filter) -> bool {
+		self.enlarge_filter = enlarge;
+		self.reduce_filter = reduce;
+		self.image.is_some()
+	}
+
 	pub fn draw(
 		&mut self,
 		allocation: &gtk::Rectangle,
 		scale_factor: i32,
 		context: &cairo::Context,
+		loupe: Option<Loupe>,
 	) {
 		let started = self.startup.begin.elapsed();
 		let surface = cairo::ImageSurface::create(
@@ -406,10 +1167,14 @@ impl ImageDraw {
 
 		self.draw_image(allocation, &context2);
 
+		if let Some(loupe) = loupe {
+			self.draw_loupe(allocation, &context2, loupe);
+		}
+
 		context.set_source_surface(&surface, 0.0, 0.0).unwrap();
 		context.paint().unwrap();
 
-		if !self.startup.draw && !self.waiting {
+		if !self.startup.draw && self.waiting != Waiting::Image {
 			self.startup.draw = true;
 
 			trace!("First image draw started at {started:?}");
@@ -433,21 +1198,192 @@ impl ImageDraw {
 	}
 
 	fn draw_image(&mut self, allocation: &gtk::Rectangle, context: &cairo::Context) {
+		// The allocation isn't known until the image is drawn
+		match self.restore_zoom.take() {
+			Some(ZoomMode::Actual) => {
+				let centre = PointI32::from((allocation.width() / 2, allocation.height() / 2));
+
+				self.zoom(allocation, centre, |_| Sf64::actual());
+			}
+			Some(ZoomMode::FitWidth) => self.fit(allocation, Fit::Width),
+			Some(ZoomMode::FitHeight) => self.fit(allocation, Fit::Height),
+			Some(ZoomMode::Fill) => self.fit(allocation, Fit::Fill),
+			Some(ZoomMode::Fit) | None => (),
+		}
+
+		let draw_at = self.calc_draw_position(allocation, true);
+
+		self.scale = draw_at.as_ref().map(|draw_at| draw_at.scale);
+
+		let Some(draw_at) = draw_at else {
+			return;
+		};
+
+		if let Some(image) = &self.image {
+			self.orientation = image.orientation();
+		}
+
+		context.save().unwrap();
+		self.paint_image(context, &draw_at);
+		context.restore().unwrap();
+
+		if let Some(failure) = self.image.as_ref().and_then(|image| image.failure()) {
+			Self::draw_failure(allocation, context, &failure);
+		}
+
+		if let Some(image) = &self.image
+			&& let Some(partial) = image.partial()
+		{
+			Self::draw_badge(
+				context,
+				if partial.rows < image.height() {
+					"Truncated"
+				} else {
+					"Corrupt"
+				},
+			);
+		}
+	}
+
+	/// Draw a label in the top left corner of the pane
+	fn draw_badge(context: &cairo::Context, text: &str) {
+		const PADDING: f64 = 4.0;
+		const MARGIN: f64 = 8.0;
+
+		context.save().unwrap();
+		context.select_font_face(
+			"sans-serif",
+			cairo::FontSlant::Normal,
+			cairo::FontWeight::Bold,
+		);
+		context.set_font_size(13.0);
+
+		let font = context.font_extents().unwrap();
+		let width = context.text_extents(text).unwrap().x_advance() + PADDING * 2.0;
+		let height = font.ascent() + font.descent() + PADDING * 2.0;
+
+		context.set_source_rgba(0.75, 0.0, 0.0, 0.75);
+		context.rectangle(MARGIN, MARGIN, width, height);
+		context.fill().unwrap();
+
+		context.set_source_rgb(1.0, 1.0, 1.0);
+		context.move_to(MARGIN + PADDING, MARGIN + PADDING + font.ascent());
+		context.show_text(text).unwrap();
+		context.restore().unwrap();
+	}
+
+	/// Draw the reason that the image couldn't be opened or decoded in the
+	/// middle of the pane, wrapped to fit the width of the pane
+	fn draw_failure(allocation: &gtk::Rectangle, context: &cairo::Context, failure: &Failure) {
+		const PADDING: f64 = 8.0;
+
+		context.save().unwrap();
+		context.select_font_face(
+			"monospace",
+			cairo::FontSlant::Normal,
+			cairo::FontWeight::Normal,
+		);
+		context.set_font_size(13.0);
+
+		let font = context.font_extents().unwrap();
+		let line_height = font.ascent() + font.descent();
+		let text_width = |text: &str| context.text_extents(text).unwrap().x_advance();
+		let available = f64::from(allocation.width()) - PADDING * 2.0;
+		let lines: Vec<String> = [
+			failure.codec.unwrap_or("Unknown format"),
+			failure.error.as_str(),
+		]
+		.iter()
+		.flat_map(|text| wrap_text(text, |line| text_width(line) <= available))
+		.collect();
+		let width = lines
+			.iter()
+			.map(|line| text_width(line))
+			.fold(0.0, f64::max)
+			+ PADDING * 2.0;
+		#[expect(clippy::cast_precision_loss, reason = "There aren't that many lines")]
+		let height = line_height * lines.len() as f64 + PADDING * 2.0;
+		let x = ((f64::from(allocation.width()) - width) / 2.0).max(0.0);
+		let y = ((f64::from(allocation.height()) - height) / 2.0).max(0.0);
+
+		context.set_source_rgba(0.0, 0.0, 0.0, 0.75);
+		context.rectangle(x, y, width, height);
+		context.fill().unwrap();
+
+		context.set_source_rgb(1.0, 1.0, 1.0);
+
+		let mut baseline = y + PADDING + font.ascent();
+
+		for line in &lines {
+			context.move_to(x + PADDING, baseline);
+			context.show_text(line).unwrap();
+			baseline += line_height;
+		}
+		context.restore().unwrap();
+	}
+
+	/// Draw a circle around the pointer with the image at the loupe scale,
+	/// so that the same part of the image is under the pointer
+	///
+	/// The loupe scale is in device pixels, so that 100% shows each pixel of
+	/// the image as one pixel of the display
+	fn draw_loupe(&mut self, allocation: &gtk::Rectangle, context: &cairo::Context, loupe: Loupe) {
+		/// Radius in logical pixels, which are multiplied by the scale factor
+		/// like the rest of the drawing area so that the loupe is the same size
+		/// on `HiDPI` displays
+		const RADIUS: f64 = 100.0;
+
 		let Some(draw_at) = self.calc_draw_position(allocation, true) else {
 			return;
 		};
+		let scale_factor = context.target().device_scale().0;
+		let scale = Sf64::try_from(f64::from(loupe.scale) / scale_factor).unwrap_or(loupe.scale);
+		let draw_at = DrawAt {
+			position: zoom_position(loupe.pointer, draw_at.position, draw_at.scale, scale),
+			scale,
+			..draw_at
+		};
+		let x = f64::from(loupe.pointer.x);
+		let y = f64::from(loupe.pointer.y);
+
+		context.save().unwrap();
+		context.arc(x, y, RADIUS, 0.0, std::f64::consts::TAU);
+		context.clip();
+		context.set_source_rgb(0.0, 0.0, 0.0);
+		context.paint().unwrap();
+		self.paint_image(context, &draw_at);
+		context.restore().unwrap();
+
+		context.save().unwrap();
+		context.arc(x, y, RADIUS, 0.0, std::f64::consts::TAU);
+		context.set_source_rgb(1.0, 1.0, 1.0);
+		context.set_line_width(2.0);
+		context.stroke().unwrap();
+		context.restore().unwrap();
+	}
 
+	fn paint_image(&mut self, context: &cairo::Context, draw_at: &DrawAt) {
 		let Some(image) = &self.image else {
 			return;
 		};
 
-		self.orientation = image.orientation();
-
 		context.translate(draw_at.position.x.into(), draw_at.position.y.into());
 		context.scale(draw_at.scale.into(), draw_at.scale.into());
 
-		image.with_surface(|surface, loaded| {
-			self.waiting = surface.is_none();
+		// Scale of image pixels to device pixels
+		let device_scale = f64::from(draw_at.scale) * context.target().device_scale().0;
+		let filter = if device_scale >= 1.0 {
+			self.enlarge_filter
+		} else {
+			self.reduce_filter
+		};
+
+		let reduced = image.with_surface(device_scale, |surface, loaded| {
+			self.waiting = if surface.is_none() {
+				Waiting::Image
+			} else {
+				Waiting::Nothing
+			};
 
 			if let Some(surface) = surface {
 				match self.orientation.rotate {
@@ -474,10 +1410,18 @@ impl ImageDraw {
 					context.scale(-1.0, 1.0);
 				}
 
+				// The surface is smaller than the image if it's a mipmap
+				context.save().unwrap();
+				context.scale(
+					f64::from(image.width()) / f64::from(surface.width()),
+					f64::from(image.height()) / f64::from(surface.height()),
+				);
+
 				let pattern = cairo::SurfacePattern::create(surface);
-				pattern.set_filter(cairo::Filter::Fast);
+				pattern.set_filter(filter);
 				context.set_source(pattern).unwrap();
 				context.paint().unwrap();
+				context.restore().unwrap();
 
 				// Release the `surface` after using it, before this closure
 				// returns otherwise `context` will still have a reference to it
@@ -486,36 +1430,11 @@ impl ImageDraw {
 				if self.af_points
 					&& let Some(af_points) = &image.metadata.af_points
 				{
-					let dashes = [(5.0 / draw_at.scale).into(); 2];
-					let dots = [(2.0 / draw_at.scale).into(); 2];
-
-					context.save().unwrap();
-					context.set_operator(cairo::Operator::Difference);
-
-					for af_point in af_points {
-						if af_point.active {
-							context.set_source_rgb(1.0, 0.0, 1.0);
-							context.set_line_width((4.0 / draw_at.scale).into());
-							context.set_dash(&[], 0.0);
-						} else if af_point.selected {
-							context.set_source_rgb(1.0, 0.0, 0.0);
-							context.set_line_width((2.0 / draw_at.scale).into());
-							context.set_dash(&dots, 0.0);
-						} else {
-							context.set_source_rgb(1.0, 1.0, 1.0);
-							context.set_line_width((1.0 / draw_at.scale).into());
-							context.set_dash(&dashes, 0.0);
-						}
-						context.rectangle(
-							af_point.position.x.into(),
-							af_point.position.y.into(),
-							af_point.dimensions.width.into(),
-							af_point.dimensions.height.into(),
-						);
-						context.stroke().unwrap();
-					}
+					Self::draw_af_points(context, af_points, draw_at.scale);
+				}
 
-					context.restore().unwrap();
+				if let Some(partial) = image.partial() {
+					Self::draw_missing(context, image, &partial);
 				}
 			} else {
 				if loaded {
@@ -533,6 +1452,102 @@ impl ImageDraw {
 				context.paint().unwrap();
 			}
 		});
+
+		if reduced {
+			self.waiting = Waiting::FullResolution;
+		}
+	}
+
+	/// Mark the rows at the bottom of a partly decoded image that are missing
+	fn draw_missing(context: &cairo::Context, image: &Image, partial: &Partial) {
+		let top = f64::from(partial.rows);
+		let bottom = f64::from(image.height());
+
+		if top >= bottom {
+			return;
+		}
+
+		context.save().unwrap();
+		context.rectangle(0.0, top, image.width().into(), bottom - top);
+		context.set_source_rgba(0.75, 0.0, 0.0, 0.25);
+		context.fill().unwrap();
+		context.restore().unwrap();
+	}
+
+	/// Describe the pixel at a position in the pane, using the coordinates of
+	/// the pixel in the image file before it was oriented
+	pub fn inspect(&mut self, allocation: &gtk::Allocation, point: PointF64) -> Option<String> {
+		let draw_at = self.calc_draw_position(allocation, true)?;
+		let image = self.image.as_ref()?;
+		let (x, y) = image_pixel(
+			image,
+			self.orientation,
+			(point - draw_at.position) / draw_at.scale,
+		)?;
+		let mut text = format!("{x},{y}");
+
+		if let Some((pixel, exact)) = image.pixel(x, y) {
+			let [_, red, green, blue] = pixel.to_be_bytes();
+
+			write!(
+				text,
+				" R {red} G {green} B {blue} #{:06x}",
+				pixel & 0x00ff_ffff
+			)
+			.unwrap();
+
+			if let Some(bit_depth) = image.metadata.bit_depth {
+				write!(text, " ({bit_depth}-bit)").unwrap();
+			}
+
+			// The image hasn't been loaded at full resolution yet
+			if !exact {
+				text.push_str(" (approximate)");
+			}
+		}
+
+		Some(text)
+	}
+
+	fn draw_af_points(context: &cairo::Context, af_points: &[AFPoint], scale: Sf64) {
+		let dashes = [(5.0 / scale).into(); 2];
+		let dots = [(2.0 / scale).into(); 2];
+
+		context.save().unwrap();
+		context.set_operator(cairo::Operator::Difference);
+
+		for af_point in af_points {
+			if af_point.active {
+				context.set_source_rgb(1.0, 0.0, 1.0);
+				context.set_line_width((4.0 / scale).into());
+				context.set_dash(&[], 0.0);
+			} else if af_point.selected {
+				context.set_source_rgb(1.0, 0.0, 0.0);
+				context.set_line_width((2.0 / scale).into());
+				context.set_dash(&dots, 0.0);
+			} else {
+				context.set_source_rgb(1.0, 1.0, 1.0);
+				context.set_line_width((1.0 / scale).into());
+				context.set_dash(&dashes, 0.0);
+			}
+			context.rectangle(
+				af_point.position.x.into(),
+				af_point.position.y.into(),
+				af_point.dimensions.width.into(),
+				af_point.dimensions.height.into(),
+			);
+			context.stroke().unwrap();
+		}
+
+		context.restore().unwrap();
+	}
+
+	/// Dimensions of the image after it has been rotated
+	fn dimensions(image: &Image, orientation: Orientation) -> DimensionsF64 {
+		match orientation.rotate {
+			Rotate::Rotate0 | Rotate::Rotate180 => image.metadata.dimensions.into(),
+			Rotate::Rotate90 | Rotate::Rotate270 => image.metadata.dimensions.rotate90().into(),
+		}
 	}
 
 	fn calc_draw_position(
@@ -545,12 +1560,15 @@ impl ImageDraw {
 		};
 
 		let output: DimensionsF64 = allocation.into();
-		let input: DimensionsF64 = match self.orientation.rotate {
-			Rotate::Rotate0 | Rotate::Rotate180 => image.metadata.dimensions.into(),
-			Rotate::Rotate90 | Rotate::Rotate270 => image.metadata.dimensions.rotate90().into(),
+		let input = Self::dimensions(image, self.orientation);
+
+		let fixed_scale = match (self.zoom.scale, self.zoom.fit) {
+			(Some(scale), _) => Some(scale),
+			(None, Fit::Best) => None,
+			(None, fit) => Some(fit_scale(input, output, fit)),
 		};
 
-		let (scale, position, target_position) = if let Some(scale) = self.zoom.scale {
+		let (scale, position, target_position) = if let Some(scale) = fixed_scale {
 			/// Allow zooming in without changing position, but constrain drag
 			/// operations so that they can't move further away from the centre
 			fn constrain_value<T: XYf64<T>>(
@@ -637,7 +1655,7 @@ impl ImageDraw {
 		} else {
 			let width_ratio = Sf64::ratio(output.width, input.width);
 			let height_ratio = Sf64::ratio(output.height, input.height);
-			let scale = Sf64::min(width_ratio, height_ratio);
+			let scale = fit_scale(input, output, Fit::Best);
 			let input = input * scale;
 
 			let position = if width_ratio >= height_ratio {
@@ -658,3 +1676,261 @@ impl ImageDraw {
 		})
 	}
 }
+
+/// Scale that makes the image fit the window
+fn fit_scale(input: DimensionsF64, output: DimensionsF64, fit: Fit) -> Sf64 {
+	let width_ratio = Sf64::ratio(output.width, input.width);
+	let height_ratio = Sf64::ratio(output.height, input.height);
+
+	match fit {
+		Fit::Best => Sf64::min(width_ratio, height_ratio),
+		Fit::Width => width_ratio,
+		Fit::Height => height_ratio,
+		Fit::Fill => Sf64::max(width_ratio, height_ratio),
+	}
+	.clamp(MIN_ZOOM, MAX_ZOOM)
+}
+
+/// Split text into lines that `fit`, at spaces where possible
+///
+/// Words that don't fit on a line by themselves (e.g. long paths) are split
+/// wherever they need to be.
+fn wrap_text(text: &str, fits: impl Fn(&str) -> bool) -> Vec<String> {
+	let mut lines = Vec::new();
+
+	for paragraph in text.lines() {
+		let mut line = String::new();
+
+		for word in paragraph.split(' ') {
+			let joined = if line.is_empty() {
+				word.to_owned()
+			} else {
+				format!("{line} {word}")
+			};
+
+			if fits(&joined) {
+				line = joined;
+				continue;
+			}
+
+			if !line.is_empty() {
+				lines.push(mem::take(&mut line));
+			}
+
+			for char in word.chars() {
+				line.push(char);
+
+				if !fits(&line) && line.chars().nth(1).is_some() {
+					line.pop();
+					lines.push(mem::replace(&mut line, char.to_string()));
+				}
+			}
+		}
+
+		lines.push(line);
+	}
+
+	lines
+}
+
+/// Pixel in the image (before it was oriented) at a point in the oriented
+/// image, if it's inside the image
+fn image_pixel(image: &Image, orientation: Orientation, point: PointF64) -> Option<(Xu32, Yu32)> {
+	let width = f64::from(image.width());
+	let height = f64::from(image.height());
+	let (x, y) = (f64::from(point.x), f64::from(point.y));
+
+	// Reverse the transformations in `ImageDraw::draw_image()`
+	let (x, y) = match orientation.rotate {
+		Rotate::Rotate0 => (x, y),
+		Rotate::Rotate90 => (y, height - x),
+		Rotate::Rotate180 => (width - x, height - y),
+		Rotate::Rotate270 => (width - y, x),
+	};
+	let x = if orientation.horizontal_flip {
+		width - x
+	} else {
+		x
+	};
+
+	if !(0.0..width).contains(&x) || !(0.0..height).contains(&y) {
+		return None;
+	}
+
+	#[expect(
+		clippy::cast_possible_truncation,
+		clippy::cast_sign_loss,
+		reason = "Checked that the position is inside the image"
+	)]
+	Some((Xu32::from(x as u32), Yu32::from(y as u32)))
+}
+
+/// Position of an image that was at `position` with scale `from`, so that the
+/// same part of it is under `pointer` with scale `to`
+fn zoom_position(pointer: PointF64, position: PointF64, from: Sf64, to: Sf64) -> PointF64 {
+	pointer - ((pointer - position) / from * to)
+}
+
+/// Scale for a zoom gesture, applying the ratio to the scale when the gesture
+/// began so that changes in direction don't accumulate errors
+fn gesture_zoom_scale(begin: Sf64, ratio: f64) -> Sf64 {
+	Sf64::try_from((f64::from(begin) * ratio).clamp(MIN_ZOOM.into(), MAX_ZOOM.into()))
+		.unwrap_or(begin)
+}
+
+/// Zoom factor for a smooth scroll distance, where a distance of 1 is one step
+/// of a scroll wheel
+fn scroll_zoom_factor(distance: f64) -> Sf64 {
+	Sf64::try_from(
+		f64::from(SCROLL_ZOOM_FACTOR)
+			.powf(-distance)
+			.clamp(MIN_ZOOM.into(), MAX_ZOOM.into()),
+	)
+	.unwrap_or(Sf64::actual())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(actual: f64, expected: f64) {
+		assert!(
+			(actual - expected).abs() <= expected.abs().max(1.0) * 1e-9,
+			"{actual} != {expected}"
+		);
+	}
+
+	fn assert_point_close(actual: PointF64, expected: PointF64) {
+		assert_close(actual.x.into(), expected.x.into());
+		assert_close(actual.y.into(), expected.y.into());
+	}
+
+	fn scale(value: f64) -> Sf64 {
+		Sf64::try_from(value).unwrap()
+	}
+
+	#[test]
+	fn zoom_position_keeps_image_under_pointer() {
+		let pointer = PointF64::from((300.0, 200.0));
+		let position = PointF64::from((-150.0, 40.0));
+
+		for (from, to) in [(1.0, 2.0), (2.0, 0.5), (0.25, 3.0), (1.0, 1.0)] {
+			let zoomed = zoom_position(pointer, position, scale(from), scale(to));
+
+			// Image coordinates of the pointer are the same at both scales
+			assert_point_close(
+				(pointer - position) / scale(from),
+				(pointer - zoomed) / scale(to),
+			);
+		}
+	}
+
+	#[test]
+	fn zoom_position_at_image_origin() {
+		let position = PointF64::from((25.0, -75.0));
+
+		assert_point_close(
+			zoom_position(position, position, scale(1.0), scale(4.0)),
+			position,
+		);
+	}
+
+	#[test]
+	fn zoom_position_round_trip() {
+		let pointer = PointF64::from((10.0, 500.0));
+		let position = PointF64::from((-1000.0, -20.0));
+		let zoomed = zoom_position(pointer, position, scale(1.0), scale(3.0_f64.sqrt()));
+
+		assert_point_close(
+			zoom_position(pointer, zoomed, scale(3.0_f64.sqrt()), scale(1.0)),
+			position,
+		);
+	}
+
+	#[test]
+	fn gesture_zoom_is_relative_to_begin() {
+		let begin = scale(2.0);
+
+		// The scale changed signal reports the cumulative ratio
+		for ratio in [1.1, 1.2, 1.5] {
+			assert_close(gesture_zoom_scale(begin, ratio).into(), 2.0 * ratio);
+		}
+
+		// Changing direction returns to the original scale
+		for ratio in [1.4, 0.8, 1.0] {
+			assert_close(gesture_zoom_scale(begin, ratio).into(), 2.0 * ratio);
+		}
+		assert_eq!(gesture_zoom_scale(begin, 1.0), begin);
+	}
+
+	#[test]
+	fn gesture_zoom_limits() {
+		assert_eq!(gesture_zoom_scale(scale(1.0), 0.0), MIN_ZOOM);
+		assert_eq!(gesture_zoom_scale(MAX_ZOOM, 2.0), MAX_ZOOM);
+		assert_eq!(gesture_zoom_scale(scale(1.0), f64::INFINITY), MAX_ZOOM);
+		assert_eq!(gesture_zoom_scale(scale(1.0), f64::NAN), scale(1.0));
+	}
+
+	#[test]
+	fn fit_scales() {
+		let dimensions = |width: f64, height: f64| {
+			DimensionsF64::new(
+				Xf64::try_from(width).unwrap(),
+				Yf64::try_from(height).unwrap(),
+			)
+		};
+		let input = dimensions(4000.0, 1000.0);
+		let output = dimensions(800.0, 400.0);
+
+		assert_close(fit_scale(input, output, Fit::Best).into(), 0.2);
+		assert_close(fit_scale(input, output, Fit::Width).into(), 0.2);
+		assert_close(fit_scale(input, output, Fit::Height).into(), 0.4);
+		assert_close(fit_scale(input, output, Fit::Fill).into(), 0.4);
+	}
+
+	#[test]
+	fn wrap_text_to_width() {
+		let fits = |line: &str| line.chars().count() <= 10;
+
+		assert_eq!(wrap_text("Short", fits), ["Short"]);
+		assert_eq!(
+			wrap_text("Not a JPEG file: starts with 0x89 0x50", fits),
+			["Not a JPEG", "file:", "starts", "with 0x89", "0x50"]
+		);
+		assert_eq!(
+			wrap_text("/a/very/long/path/name.jpg: error", fits),
+			["/a/very/lo", "ng/path/na", "me.jpg:", "error"]
+		);
+		assert_eq!(wrap_text("One\nTwo", fits), ["One", "Two"]);
+		assert_eq!(wrap_text("", fits), Vec::<String>::new());
+	}
+
+	#[test]
+	fn scroll_zoom_steps() {
+		assert_close(scroll_zoom_factor(-1.0).into(), SCROLL_ZOOM_FACTOR.into());
+		assert_close(
+			scroll_zoom_factor(1.0).into(),
+			(1.0 / SCROLL_ZOOM_FACTOR).into(),
+		);
+		assert_eq!(scroll_zoom_factor(0.0), Sf64::actual());
+	}
+
+	#[test]
+	fn scroll_zoom_proportional() {
+		let half = f64::from(scroll_zoom_factor(-0.5));
+
+		assert_close(half * half, SCROLL_ZOOM_FACTOR.into());
+
+		// Scrolling back by the same distance returns to the original scale
+		let there = f64::from(scroll_zoom_factor(-0.3));
+		let back = f64::from(scroll_zoom_factor(0.3));
+
+		assert_close(there * back, 1.0);
+	}
+
+	#[test]
+	fn scroll_zoom_limits() {
+		assert_eq!(scroll_zoom_factor(-1e6), MAX_ZOOM);
+		assert_eq!(scroll_zoom_factor(1e6), MIN_ZOOM);
+	}
+}